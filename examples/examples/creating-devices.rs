@@ -5,150 +5,196 @@ extern crate alloc;
 
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use wdk::device::{
-    dispatch_device, Access, Completion, Device, DeviceDoFlags, DeviceFlags, DeviceOperations,
-    DeviceType, RequestError,
-};
+use wdk::device::{Completion, Device, DeviceOperations, DeviceType, RequestError};
 use wdk::driver::Driver;
 use wdk::error::Error;
-use wdk::ioctl::RequiredAccess;
-use wdk::request::{IoControlRequest, IoRequest, ReadRequest, WriteRequest};
-use wdk::{println, unicode_string};
-use wdk_sys::base::{
-    DRIVER_OBJECT, IRP_MJ_MAXIMUM_FUNCTION, NTSTATUS, STATUS_SUCCESS, UNICODE_STRING,
+use wdk::ioctl::{
+    ioctl, ControlCode, IoctlRouter, FILE_ANY_ACCESS, FILE_DEVICE_UNKNOWN, FILE_READ_DATA,
+    FILE_WRITE_DATA, METHOD_BUFFERED,
 };
-
-const IOCTL_PRINT_VALUE: u32 = 0x800;
-const IOCTL_READ_VALUE: u32 = 0x801;
-const IOCTL_WRITE_VALUE: u32 = 0x802;
+use wdk::module::KernelModule;
+use wdk::request::{IoControlRequest, IoRequest, ReadRequest, WriteRequest};
+use wdk::string::UnicodeString;
+use wdk::sync::FastMutex;
+use wdk::{kernel_module, println};
+
+const IOCTL_PRINT_VALUE: ControlCode =
+    ioctl!(FILE_DEVICE_UNKNOWN, 0x800, METHOD_BUFFERED, FILE_ANY_ACCESS);
+const IOCTL_READ_VALUE: ControlCode =
+    ioctl!(FILE_DEVICE_UNKNOWN, 0x801, METHOD_BUFFERED, FILE_READ_DATA);
+const IOCTL_WRITE_VALUE: ControlCode =
+    ioctl!(FILE_DEVICE_UNKNOWN, 0x802, METHOD_BUFFERED, FILE_WRITE_DATA);
+
+const MAX_DATA_SIZE: usize = 4096;
+
+#[derive(Default)]
+struct Client {
+    ioctls: AtomicU32,
+}
 
 struct MyDevice {
-    data: Vec<u8>,
-    value: u32,
+    data: FastMutex<Vec<u8>>,
+    value: AtomicU32,
 }
 
 impl MyDevice {
-    fn print_value(&mut self, _request: &IoControlRequest) -> Result<u32, Error> {
-        println!("value: {}", self.value);
+    fn print_value(&self) -> Result<(), Error> {
+        println!("value: {}", self.value.load(Ordering::Relaxed));
 
-        Ok(0)
+        Ok(())
     }
 
-    fn read_value(&mut self, request: &IoControlRequest) -> Result<u32, Error> {
-        let mut user_ptr = request.user_ptr();
-
-        user_ptr.write(&self.value)?;
-
-        Ok(core::mem::size_of::<u32>() as u32)
+    fn read_value(&self) -> Result<u32, Error> {
+        Ok(self.value.load(Ordering::Relaxed))
     }
 
-    fn write_value(&mut self, request: &IoControlRequest) -> Result<u32, Error> {
-        let user_ptr = request.user_ptr();
+    fn write_value(&self, value: u32) -> Result<(), Error> {
+        self.value.store(value, Ordering::Relaxed);
 
-        self.value = user_ptr.read()?;
-
-        Ok(0)
+        Ok(())
     }
 }
 
 impl DeviceOperations for MyDevice {
-    fn create(&mut self, _device: &Device, request: IoRequest) -> Result<Completion, RequestError> {
+    type FileContext = Client;
+
+    fn create(
+        &self,
+        _device: &Device,
+        context: &mut Option<Client>,
+        request: IoRequest,
+    ) -> Result<Completion, RequestError> {
         println!("userspace opened the device");
 
+        *context = Some(Client::default());
+
         Ok(Completion::Complete(0, request))
     }
 
-    fn close(&mut self, _device: &Device, request: IoRequest) -> Result<Completion, RequestError> {
+    fn close(
+        &self,
+        _device: &Device,
+        _file: Option<&Client>,
+        request: IoRequest,
+    ) -> Result<Completion, RequestError> {
         println!("userspace closed the device");
 
         Ok(Completion::Complete(0, request))
     }
 
     fn cleanup(
-        &mut self,
+        &self,
         _device: &Device,
+        file: Option<&Client>,
         request: IoRequest,
     ) -> Result<Completion, RequestError> {
-        println!("device is no longer in use by userspace");
+        if let Some(file) = file {
+            println!(
+                "device is no longer in use by userspace after {} ioctls",
+                file.ioctls.load(Ordering::Relaxed)
+            );
+        }
 
         Ok(Completion::Complete(0, request))
     }
 
-    fn read(&mut self, _device: &Device, request: ReadRequest) -> Result<Completion, RequestError> {
+    fn read(
+        &self,
+        _device: &Device,
+        _file: Option<&Client>,
+        mut request: ReadRequest,
+    ) -> Result<Completion, RequestError> {
+        let data = self.data.lock().unwrap();
+
+        let offset = (request.byte_offset() as usize).min(data.len());
+
         let mut user_ptr = request.user_ptr();
-        let slice = user_ptr.as_mut_slice();
+        let mut writer = user_ptr.writer();
+
+        if let Err(e) = writer.write_bytes(&data[offset..]) {
+            return Err(RequestError(e, request.into()));
+        }
 
-        let offset = (request.offset() as usize).min(self.data.len());
-        let size = slice.len().min(self.data.len() - offset);
+        let written = writer.written();
 
-        slice[0..size].copy_from_slice(&self.data[offset..offset + size]);
+        request.advance_file_position(written);
 
-        Ok(Completion::Complete(size as u32, request.into()))
+        Ok(Completion::Complete(written, request.into()))
     }
 
     fn write(
-        &mut self,
+        &self,
         _device: &Device,
-        request: WriteRequest,
+        _file: Option<&Client>,
+        mut request: WriteRequest,
     ) -> Result<Completion, RequestError> {
+        let mut data = self.data.lock().unwrap();
+
+        // Writes may overwrite and extend the data, but not leave a gap after it.
+        let offset = match usize::try_from(request.byte_offset()) {
+            Ok(offset) if offset <= data.len() => offset,
+            _ => return Err(RequestError(Error::INVALID_PARAMETER, request.into())),
+        };
+
         let user_ptr = request.user_ptr();
 
-        if request.offset() > 0 {
-            return Err(RequestError(Error::END_OF_FILE, request.into()))?;
+        let slice = match user_ptr.as_slice::<u8>() {
+            Ok(slice) => slice,
+            Err(e) => return Err(RequestError(e, request.into())),
+        };
+        let size = slice.len().min(MAX_DATA_SIZE.saturating_sub(offset));
+
+        if offset + size > data.len() {
+            data.resize(offset + size, 0);
         }
 
-        let slice = user_ptr.as_slice();
-        let size = slice.len().min(4096);
+        data[offset..offset + size].copy_from_slice(&slice[..size]);
 
-        self.data = slice[0..size].to_vec();
+        request.advance_file_position(size);
 
-        Ok(Completion::Complete(size as u32, request.into()))
+        Ok(Completion::Complete(size, request.into()))
     }
 
     fn ioctl(
-        &mut self,
+        &self,
         _device: &Device,
+        file: Option<&Client>,
         request: IoControlRequest,
     ) -> Result<Completion, RequestError> {
-        let result = match request.function() {
-            (_, IOCTL_PRINT_VALUE) => self.print_value(&request),
-            (RequiredAccess::READ_DATA, IOCTL_READ_VALUE) => self.read_value(&request),
-            (RequiredAccess::WRITE_DATA, IOCTL_WRITE_VALUE) => self.write_value(&request),
-            _ => Err(Error::INVALID_PARAMETER),
-        };
-
-        match result {
-            Ok(size) => Ok(Completion::Complete(size, request.into())),
-            Err(e) => Err(RequestError(e, request.into())),
+        if let Some(file) = file {
+            file.ioctls.fetch_add(1, Ordering::Relaxed);
         }
+
+        IoctlRouter::new(request)
+            .route(IOCTL_PRINT_VALUE, |_: &()| self.print_value())
+            .route(IOCTL_READ_VALUE, |_: &()| self.read_value())
+            .route(IOCTL_WRITE_VALUE, |value: &u32| self.write_value(*value))
+            .finish()
     }
 }
 
-#[no_mangle]
-extern "system" fn driver_entry(driver: &mut DRIVER_OBJECT, _: &UNICODE_STRING) -> NTSTATUS {
-    driver.DriverUnload = Some(driver_exit);
-
-    let mut drv = unsafe { Driver::from_raw(driver) };
-
-    drv.create_device(
-        &mut unicode_string!("\\Device\\Example"),
-        DeviceType::Unknown,
-        DeviceFlags::SECURE_OPEN,
-        DeviceDoFlags::DO_BUFFERED_IO,
-        Access::NonExclusive,
-        MyDevice {
-            data: vec![],
-            value: 0,
-        },
-    )
-    .unwrap();
-
-    for i in 0..IRP_MJ_MAXIMUM_FUNCTION {
-        driver.MajorFunction[i as usize] = Some(dispatch_device);
+struct Example;
+
+impl KernelModule for Example {
+    fn init(driver: &mut Driver, _registry_path: &UnicodeString) -> Result<Self, Error> {
+        let device = driver
+            .device_builder()
+            .name("\\Device\\Example")
+            .symbolic_link("\\??\\Example")
+            .device_type(DeviceType::Unknown)
+            .buffered_io()
+            .build(MyDevice {
+                data: FastMutex::new(vec![])?,
+                value: AtomicU32::new(0),
+            })?;
+
+        // The driver keeps the device and its symbolic link alive until it is unloaded.
+        driver.register_device(device)?;
+
+        Ok(Example)
     }
-
-    STATUS_SUCCESS
 }
 
-extern "stdcall" fn driver_exit(_driver: *mut DRIVER_OBJECT) {}
+kernel_module!(Example);
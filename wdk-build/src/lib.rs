@@ -84,6 +84,7 @@ pub fn build() -> Result<(), Error> {
     println!("cargo:rustc-link-arg=/DRIVER");
     println!("cargo:rustc-link-arg=/DYNAMICBASE");
     println!("cargo:rustc-link-arg=/MANIFEST:NO");
+    // The entry point is generated by `wdk::kernel_module!`.
     println!("cargo:rustc-link-arg=/ENTRY:driver_entry");
     println!("cargo:rustc-link-arg=/MERGE:.edata=.rdata");
     println!("cargo:rustc-link-arg=/MERGE:.rustc=.data");
@@ -0,0 +1,128 @@
+use core::fmt;
+
+/// A globally unique identifier, laid out like `GUID`, that can be built in `const` contexts, e.g.
+/// for the device interface classes that a driver and its clients share.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+/// The error returned by [`Guid::parse`] for a string that is not a GUID.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseGuidError;
+
+impl fmt::Display for ParseGuidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid GUID")
+    }
+}
+
+impl Guid {
+    /// Creates a GUID from the string form `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, optionally
+    /// enclosed in braces. This is meant to be used in `const` items, where an invalid GUID is a
+    /// compile error:
+    ///
+    /// ```
+    /// use wdk_ioctl::Guid;
+    ///
+    /// const GUID_DEVINTERFACE_EXAMPLE: Guid = Guid::new("{5D006E1A-2631-466C-B8A0-32FD498E4424}");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the string is not a valid GUID.
+    pub const fn new(s: &str) -> Self {
+        match Self::parse(s) {
+            Ok(guid) => guid,
+            Err(_) => panic!("invalid GUID"),
+        }
+    }
+
+    pub const fn from_fields(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
+        Self {
+            data1,
+            data2,
+            data3,
+            data4,
+        }
+    }
+
+    /// Creates a GUID from its big-endian 128-bit value, i.e. the value that reads the same as the
+    /// string form.
+    pub const fn from_u128(value: u128) -> Self {
+        Self {
+            data1: (value >> 96) as u32,
+            data2: (value >> 80) as u16,
+            data3: (value >> 64) as u16,
+            data4: (value as u64).to_be_bytes(),
+        }
+    }
+
+    pub const fn to_u128(&self) -> u128 {
+        (self.data1 as u128) << 96
+            | (self.data2 as u128) << 80
+            | (self.data3 as u128) << 64
+            | u64::from_be_bytes(self.data4) as u128
+    }
+
+    /// Parses the string form `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, optionally enclosed in
+    /// braces. Hexadecimal digits may be upper or lower case.
+    pub const fn parse(s: &str) -> Result<Self, ParseGuidError> {
+        let bytes = s.as_bytes();
+
+        let start = if bytes.len() == 38 && bytes[0] == b'{' && bytes[37] == b'}' {
+            1
+        } else if bytes.len() == 36 {
+            0
+        } else {
+            return Err(ParseGuidError);
+        };
+
+        let mut value = 0u128;
+        let mut i = 0;
+
+        while i < 36 {
+            let c = bytes[start + i];
+
+            if i == 8 || i == 13 || i == 18 || i == 23 {
+                if c != b'-' {
+                    return Err(ParseGuidError);
+                }
+            } else {
+                let digit = match c {
+                    b'0'..=b'9' => c - b'0',
+                    b'a'..=b'f' => c - b'a' + 10,
+                    b'A'..=b'F' => c - b'A' + 10,
+                    _ => return Err(ParseGuidError),
+                };
+
+                value = value << 4 | digit as u128;
+            }
+
+            i += 1;
+        }
+
+        Ok(Self::from_u128(value))
+    }
+}
+
+/// Formats the GUID in the registry form `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`.
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            self.data1, self.data2, self.data3, self.data4[0], self.data4[1]
+        )?;
+
+        for byte in &self.data4[2..] {
+            write!(f, "{:02X}", byte)?;
+        }
+
+        f.write_str("}")
+    }
+}
@@ -0,0 +1,403 @@
+//! Control codes for device I/O control requests, as built by `CTL_CODE`.
+//!
+//! This crate does not depend on the kernel or on any Windows API, such that the control codes and
+//! payload structs of a driver can be defined once, in a crate that both the driver and its
+//! user-mode clients depend on:
+//!
+//! ```
+//! use wdk_ioctl::{ioctl, ControlCode, Pod, FILE_DEVICE_UNKNOWN, FILE_READ_DATA, METHOD_BUFFERED};
+//!
+//! pub const IOCTL_READ_VALUE: ControlCode =
+//!     ioctl!(FILE_DEVICE_UNKNOWN, 0x801, METHOD_BUFFERED, FILE_READ_DATA);
+//!
+//! #[derive(Copy, Clone, Default, Pod)]
+//! #[repr(C)]
+//! pub struct ReadValueOutput {
+//!     pub value: u32,
+//! }
+//!
+//! assert_eq!(IOCTL_READ_VALUE.raw(), 0x0022_6004);
+//! ```
+//!
+//! The same goes for the [`Guid`] of a device interface class that clients look the device up by,
+//! while the [`Sddl`] validator is kept here so that it can be tested on the host.
+#![cfg_attr(not(test), no_std)]
+
+mod guid;
+mod pod;
+mod sddl;
+
+use bitflags::bitflags;
+
+pub use guid::{Guid, ParseGuidError};
+pub use pod::Pod;
+pub use sddl::{
+    ParseSddlError, Sddl, SDDL_DEVOBJ_KERNEL_ONLY, SDDL_DEVOBJ_SYS_ALL,
+    SDDL_DEVOBJ_SYS_ALL_ADM_ALL, SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RWX_RES_RWX,
+    SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RW_RES_R, SDDL_DEVOBJ_SYS_ALL_ADM_RX,
+};
+pub use wdk_macros::Pod;
+
+bitflags! {
+    pub struct RequiredAccess: u32 {
+        const ANY_ACCESS = 0x0;
+        const READ_DATA = 0x1;
+        const WRITE_DATA = 0x2;
+        const READ_WRITE_DATA = Self::READ_DATA.bits | Self::WRITE_DATA.bits;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum TransferMethod {
+    Buffered = 0,
+    InputDirect = 1,
+    OutputDirect = 2,
+    Neither = 3,
+}
+
+impl TransferMethod {
+    pub const fn from_bits(value: u32) -> Self {
+        match value & 0x3 {
+            0 => Self::Buffered,
+            1 => Self::InputDirect,
+            2 => Self::OutputDirect,
+            _ => Self::Neither,
+        }
+    }
+
+    pub const fn bits(self) -> u32 {
+        self as u32
+    }
+}
+
+impl From<u32> for TransferMethod {
+    fn from(value: u32) -> Self {
+        Self::from_bits(value)
+    }
+}
+
+impl From<TransferMethod> for u32 {
+    fn from(method: TransferMethod) -> Self {
+        method.bits()
+    }
+}
+
+/// The device type that drivers use for devices that do not belong to a predefined type.
+pub const FILE_DEVICE_UNKNOWN: u16 = 0x22;
+
+pub const METHOD_BUFFERED: TransferMethod = TransferMethod::Buffered;
+pub const METHOD_IN_DIRECT: TransferMethod = TransferMethod::InputDirect;
+pub const METHOD_OUT_DIRECT: TransferMethod = TransferMethod::OutputDirect;
+pub const METHOD_NEITHER: TransferMethod = TransferMethod::Neither;
+
+pub const FILE_ANY_ACCESS: RequiredAccess = RequiredAccess::ANY_ACCESS;
+pub const FILE_READ_DATA: RequiredAccess = RequiredAccess::READ_DATA;
+pub const FILE_WRITE_DATA: RequiredAccess = RequiredAccess::WRITE_DATA;
+
+/// A control code of a device I/O control request, laid out as built by `CTL_CODE`:
+///
+/// | Bits  | Field           |
+/// |-------|-----------------|
+/// | 16-31 | device type     |
+/// | 14-15 | required access |
+/// | 2-13  | function        |
+/// | 0-1   | transfer method |
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ControlCode(u32);
+
+impl ControlCode {
+    const METHOD_BITS: usize = 2;
+    const NUM_BITS: usize = 12;
+    const ACCESS_BITS: usize = 2;
+    const TYPE_BITS: usize = 16;
+
+    const METHOD_SHIFT: usize = 0;
+    const NUM_SHIFT: usize = Self::METHOD_SHIFT + Self::METHOD_BITS;
+    const ACCESS_SHIFT: usize = Self::NUM_SHIFT + Self::NUM_BITS;
+    const TYPE_SHIFT: usize = Self::ACCESS_SHIFT + Self::ACCESS_BITS;
+
+    const METHOD_MASK: u32 = (1 << Self::METHOD_BITS) - 1;
+    const NUM_MASK: u32 = (1 << Self::NUM_BITS) - 1;
+    const ACCESS_MASK: u32 = (1 << Self::ACCESS_BITS) - 1;
+    const TYPE_MASK: u32 = (1 << Self::TYPE_BITS) - 1;
+
+    /// Builds a control code like `CTL_CODE`. Functions below `0x800` are reserved for Microsoft.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `function` does not fit in 12 bits, which is a compile error in `const` items.
+    pub const fn new(
+        device_type: u16,
+        function: u16,
+        method: TransferMethod,
+        access: RequiredAccess,
+    ) -> Self {
+        if function as u32 > Self::NUM_MASK {
+            panic!("the function of a control code is limited to 12 bits");
+        }
+
+        Self(
+            (device_type as u32) << Self::TYPE_SHIFT
+                | access.bits() << Self::ACCESS_SHIFT
+                | (function as u32) << Self::NUM_SHIFT
+                | method.bits() << Self::METHOD_SHIFT,
+        )
+    }
+
+    pub const fn from_raw(value: u32) -> Self {
+        Self(value)
+    }
+
+    pub const fn raw(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn device_type(&self) -> u16 {
+        ((self.0 >> Self::TYPE_SHIFT) & Self::TYPE_MASK) as u16
+    }
+
+    pub const fn required_access(&self) -> RequiredAccess {
+        RequiredAccess::from_bits_truncate((self.0 >> Self::ACCESS_SHIFT) & Self::ACCESS_MASK)
+    }
+
+    pub const fn function(&self) -> u16 {
+        ((self.0 >> Self::NUM_SHIFT) & Self::NUM_MASK) as u16
+    }
+
+    pub const fn number(&self) -> u32 {
+        self.function() as u32
+    }
+
+    pub const fn transfer_method(&self) -> TransferMethod {
+        TransferMethod::from_bits((self.0 >> Self::METHOD_SHIFT) & Self::METHOD_MASK)
+    }
+}
+
+impl From<u32> for ControlCode {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<ControlCode> for u32 {
+    fn from(code: ControlCode) -> Self {
+        code.0
+    }
+}
+
+/// Builds a [`ControlCode`] at compile time, with the arguments of `CTL_CODE`:
+///
+/// ```
+/// use wdk_ioctl::{ioctl, FILE_ANY_ACCESS, FILE_DEVICE_UNKNOWN, METHOD_BUFFERED};
+///
+/// let code = ioctl!(FILE_DEVICE_UNKNOWN, 0x800, METHOD_BUFFERED, FILE_ANY_ACCESS);
+/// assert_eq!(code.raw(), 0x0022_2000);
+/// ```
+#[macro_export]
+macro_rules! ioctl {
+    ($device_type:expr, $function:expr, $method:expr, $access:expr) => {{
+        const CODE: $crate::ControlCode =
+            $crate::ControlCode::new($device_type, $function, $method, $access);
+        CODE
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_ctl_code() {
+        // IOCTL_DISK_GET_DRIVE_GEOMETRY
+        let code = ioctl!(0x07, 0x000, METHOD_BUFFERED, FILE_ANY_ACCESS);
+        assert_eq!(code.raw(), 0x0007_0000);
+
+        // FSCTL_GET_RETRIEVAL_POINTERS
+        let code = ioctl!(0x09, 28, METHOD_NEITHER, FILE_ANY_ACCESS);
+        assert_eq!(code.raw(), 0x0009_0073);
+
+        // IOCTL_STORAGE_EJECT_MEDIA
+        let code = ioctl!(0x2d, 0x0202, METHOD_BUFFERED, FILE_READ_DATA);
+        assert_eq!(code.raw(), 0x002d_4808);
+    }
+
+    #[test]
+    fn round_trips() {
+        let methods = [
+            METHOD_BUFFERED,
+            METHOD_IN_DIRECT,
+            METHOD_OUT_DIRECT,
+            METHOD_NEITHER,
+        ];
+        let accesses = [
+            RequiredAccess::ANY_ACCESS,
+            RequiredAccess::READ_DATA,
+            RequiredAccess::WRITE_DATA,
+            RequiredAccess::READ_WRITE_DATA,
+        ];
+
+        for &device_type in &[0x0000, FILE_DEVICE_UNKNOWN, 0x8000, 0xffff] {
+            for &function in &[0x000, 0x7ff, 0x800, 0xfff] {
+                for &method in &methods {
+                    for &access in &accesses {
+                        let code = ControlCode::new(device_type, function, method, access);
+                        let decoded = ControlCode::from(u32::from(code));
+
+                        assert_eq!(decoded, code);
+                        assert_eq!(decoded.device_type(), device_type);
+                        assert_eq!(decoded.function(), function);
+                        assert_eq!(decoded.transfer_method(), method);
+                        assert_eq!(decoded.required_access(), access);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_every_value() {
+        for &value in &[0u32, 1, 0x0022_2003, 0xdead_beef, u32::MAX] {
+            let code = ControlCode::from_raw(value);
+
+            assert_eq!(
+                ControlCode::new(
+                    code.device_type(),
+                    code.function(),
+                    code.transfer_method(),
+                    code.required_access()
+                ),
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn derives_pod() {
+        #[derive(Copy, Clone, Pod)]
+        #[pod(crate = "crate")]
+        #[repr(C)]
+        struct Header {
+            value: u32,
+            flags: u16,
+            kind: [u8; 2],
+        }
+
+        #[derive(Copy, Clone, Pod)]
+        #[pod(crate = "crate")]
+        #[repr(transparent)]
+        struct Wrapper(Header);
+
+        fn is_pod<T: Pod>() -> usize {
+            core::mem::size_of::<T>()
+        }
+
+        assert_eq!(is_pod::<Header>(), 8);
+        assert_eq!(is_pod::<Wrapper>(), 8);
+        assert_eq!(is_pod::<[Header; 2]>(), 16);
+    }
+
+    #[test]
+    fn round_trips_guids() {
+        let s = "{5D006E1A-2631-466C-B8A0-32FD498E4424}";
+        let guid = Guid::parse(s).unwrap();
+
+        assert_eq!(guid.data1, 0x5d00_6e1a);
+        assert_eq!(guid.data2, 0x2631);
+        assert_eq!(guid.data3, 0x466c);
+        assert_eq!(guid.data4, [0xb8, 0xa0, 0x32, 0xfd, 0x49, 0x8e, 0x44, 0x24]);
+        assert_eq!(guid.to_string(), s);
+
+        assert_eq!(Guid::parse(&s[1..37]), Ok(guid));
+        assert_eq!(Guid::parse(&s.to_lowercase()), Ok(guid));
+        assert_eq!(Guid::from_u128(guid.to_u128()), guid);
+        assert_eq!(
+            Guid::from_u128(0x5d006e1a_2631_466c_b8a0_32fd498e4424),
+            guid
+        );
+
+        for &value in &[
+            0u128,
+            1,
+            u128::MAX,
+            0x0123_4567_89ab_cdef_0123_4567_89ab_cdef,
+        ] {
+            let guid = Guid::from_u128(value);
+
+            assert_eq!(Guid::parse(&guid.to_string()), Ok(guid));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_guids() {
+        for s in &[
+            "",
+            "{}",
+            "5D006E1A-2631-466C-B8A0-32FD498E442",
+            "5D006E1A-2631-466C-B8A0-32FD498E44245",
+            "{5D006E1A-2631-466C-B8A0-32FD498E4424",
+            "5D006E1A-2631-466C-B8A0-32FD498E4424}",
+            "(5D006E1A-2631-466C-B8A0-32FD498E4424)",
+            "5D006E1A+2631-466C-B8A0-32FD498E4424",
+            "5D006E1A-2631-466C-B8A032FD498E44240",
+            "5D006E1G-2631-466C-B8A0-32FD498E4424",
+            "5D006E1A-2631-466C-B8A0-32FD498E442 ",
+            "{5D006E1A-2631-466C-B8A0-32FD498E4424}0",
+        ] {
+            assert_eq!(Guid::parse(s), Err(ParseGuidError), "{}", s);
+        }
+    }
+
+    #[test]
+    fn accepts_device_sddl() {
+        for s in &[
+            SDDL_DEVOBJ_KERNEL_ONLY.as_str(),
+            SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RW_RES_R.as_str(),
+            "D:",
+            "D:PAIAR",
+            "D:ARAIP(A;;GA;;;SY)",
+            "D:(A;;0x1F01FF;;;BA)(A;;0X3;;;WD)",
+            "D:P(A;;GRGWGX;;;S-1-5-32-544)(A;;FRFW;;;S-1-15-2-1)",
+        ] {
+            assert_eq!(Sddl::parse(s).map(|sddl| sddl.as_str()), Ok(*s));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sddl() {
+        for s in &[
+            "",
+            "O:SYD:P",
+            "D:PP",
+            "D:AIAI",
+            "D:PARP",
+            "D:X",
+            "D:P(A;;GA;;;SY",
+            "D:P(D;;GA;;;SY)",
+            "D:P(A;CI;GA;;;SY)",
+            "D:P(A;;;;;SY)",
+            "D:P(A;;GZ;;;SY)",
+            "D:P(A;;0x;;;SY)",
+            "D:P(A;;0x123456789;;;SY)",
+            "D:P(A;;GA;x;;SY)",
+            "D:P(A;;GA;;;XX)",
+            "D:P(A;;GA;;;S-1-5)",
+            "D:P(A;;GA;;;S-1-5-)",
+            "D:P(A;;GA;;;SY)x",
+        ] {
+            assert_eq!(Sddl::parse(s), Err(ParseSddlError), "{}", s);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_wide_functions() {
+        ControlCode::new(
+            FILE_DEVICE_UNKNOWN,
+            0x1000,
+            METHOD_BUFFERED,
+            FILE_ANY_ACCESS,
+        );
+    }
+}
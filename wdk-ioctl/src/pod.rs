@@ -0,0 +1,72 @@
+/// A plain-old-data type, which can be copied from and to the buffers of a request as raw bytes.
+///
+/// # Safety
+///
+/// Implementors must be valid for any bit pattern, must not have padding and must not contain
+/// pointers or references. Use `#[derive(Pod)]`, which checks this at compile time, rather than
+/// implementing the trait by hand.
+///
+/// `usize` and `isize` are deliberately not `Pod`, as their size differs between a 64-bit driver
+/// and a 32-bit client. Neither are `u128` and `i128`, as their alignment differs between
+/// compilers and targets.
+///
+/// The derive rejects structs with padding:
+///
+/// ```compile_fail
+/// use wdk_ioctl::Pod;
+///
+/// #[derive(Copy, Clone, Pod)]
+/// #[repr(C)]
+/// struct Padded {
+///     flag: u8,
+///     value: u32,
+/// }
+/// ```
+///
+/// structs without a defined layout:
+///
+/// ```compile_fail
+/// use wdk_ioctl::Pod;
+///
+/// #[derive(Copy, Clone, Pod)]
+/// struct Unordered {
+///     a: u32,
+///     b: u32,
+/// }
+/// ```
+///
+/// enums, as not every bit pattern is a valid variant:
+///
+/// ```compile_fail
+/// use wdk_ioctl::Pod;
+///
+/// #[derive(Copy, Clone, Pod)]
+/// #[repr(u32)]
+/// enum Mode {
+///     Off,
+///     On,
+/// }
+/// ```
+///
+/// and generic types, whose layout depends on their parameters:
+///
+/// ```compile_fail
+/// use wdk_ioctl::Pod;
+///
+/// #[derive(Copy, Clone, Pod)]
+/// #[repr(C)]
+/// struct Wrapper<T: Pod> {
+///     value: T,
+/// }
+/// ```
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!((), u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
@@ -0,0 +1,267 @@
+use core::fmt;
+
+/// The maximum length of an SDDL string, as its UTF-16 form has to fit in a `UNICODE_STRING`.
+const MAX_LENGTH: usize = (u16::MAX / 2) as usize;
+
+/// The access rights that may be granted by an ACE.
+const RIGHTS: &[&[u8]] = &[
+    b"GA", b"GR", b"GW", b"GX", b"FA", b"FR", b"FW", b"FX", b"KA", b"KR", b"KW", b"KX", b"SD",
+    b"RC", b"WD", b"WO",
+];
+
+/// The well-known SID aliases that may be used for the trustee of an ACE.
+const SID_ALIASES: &[&[u8]] = &[
+    b"AN", b"AU", b"BA", b"BG", b"BO", b"BU", b"CO", b"IU", b"LS", b"NS", b"NU", b"PU", b"RC",
+    b"SO", b"SU", b"SY", b"WD", b"WR",
+];
+
+/// A validated SDDL string for the security descriptor of a device object, as accepted by
+/// `IoCreateDeviceSecure`.
+///
+/// Only the subset of SDDL that `IoCreateDeviceSecure` supports is accepted: a DACL (`D:`) with
+/// optional `P`, `AI` and `AR` flags, each given at most once, followed by access-allowed ACEs
+/// without ACE flags or object GUIDs, e.g. `D:P(A;;GA;;;SY)(A;;GRGWGX;;;BA)`. The validator is a
+/// `const fn`, so that a bad descriptor in a `const` item is rejected at build time rather than
+/// when the driver is loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sddl<'a>(&'a str);
+
+/// The error returned by [`Sddl::parse`] for a string that is not a valid SDDL string for a device
+/// object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseSddlError;
+
+impl fmt::Display for ParseSddlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid SDDL string for a device object")
+    }
+}
+
+/// Only the kernel and drivers can open the device.
+pub const SDDL_DEVOBJ_KERNEL_ONLY: Sddl<'static> = Sddl::new("D:P");
+
+/// The system has full access to the device.
+pub const SDDL_DEVOBJ_SYS_ALL: Sddl<'static> = Sddl::new("D:P(A;;GA;;;SY)");
+
+/// The system and administrators have full access to the device.
+pub const SDDL_DEVOBJ_SYS_ALL_ADM_ALL: Sddl<'static> = Sddl::new("D:P(A;;GA;;;SY)(A;;GA;;;BA)");
+
+/// The system has full access to the device, administrators can read and execute.
+pub const SDDL_DEVOBJ_SYS_ALL_ADM_RX: Sddl<'static> = Sddl::new("D:P(A;;GA;;;SY)(A;;GRGX;;;BA)");
+
+/// The system has full access to the device, administrators can read, write and execute, and
+/// everyone can read and write, while restricted code can only read.
+pub const SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RW_RES_R: Sddl<'static> =
+    Sddl::new("D:P(A;;GA;;;SY)(A;;GRGWGX;;;BA)(A;;GRGW;;;WD)(A;;GR;;;RC)");
+
+/// The system has full access to the device, everyone and restricted code can read, write and
+/// execute.
+pub const SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RWX_RES_RWX: Sddl<'static> =
+    Sddl::new("D:P(A;;GA;;;SY)(A;;GRGWGX;;;BA)(A;;GRGWGX;;;WD)(A;;GRGWGX;;;RC)");
+
+impl<'a> Sddl<'a> {
+    /// Creates a validated SDDL string. This is meant to be used in `const` items, where an
+    /// invalid SDDL string is a compile error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the string is not a valid SDDL string for a device object.
+    pub const fn new(s: &'a str) -> Self {
+        match Self::parse(s) {
+            Ok(sddl) => sddl,
+            Err(_) => panic!("invalid SDDL string for a device object"),
+        }
+    }
+
+    /// Validates an SDDL string for a device object.
+    pub const fn parse(s: &'a str) -> Result<Self, ParseSddlError> {
+        let bytes = s.as_bytes();
+
+        if bytes.len() > MAX_LENGTH || !starts_with(bytes, 0, b"D:") {
+            return Err(ParseSddlError);
+        }
+
+        let mut i = 2;
+
+        // The DACL flags, each of which may only be given once.
+        let mut protected = false;
+        let mut auto_inherited = false;
+        let mut auto_inherit_req = false;
+
+        while i < bytes.len() && bytes[i] != b'(' {
+            if starts_with(bytes, i, b"P") && !protected {
+                protected = true;
+                i += 1;
+            } else if starts_with(bytes, i, b"AI") && !auto_inherited {
+                auto_inherited = true;
+                i += 2;
+            } else if starts_with(bytes, i, b"AR") && !auto_inherit_req {
+                auto_inherit_req = true;
+                i += 2;
+            } else {
+                return Err(ParseSddlError);
+            }
+        }
+
+        while i < bytes.len() {
+            i = match parse_ace(bytes, i) {
+                Ok(next) => next,
+                Err(e) => return Err(e),
+            };
+        }
+
+        Ok(Self(s))
+    }
+
+    pub const fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+const fn starts_with(bytes: &[u8], at: usize, prefix: &[u8]) -> bool {
+    if at + prefix.len() > bytes.len() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < prefix.len() {
+        if bytes[at + i] != prefix[i] {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+/// Returns the length of the token at `at` if it is one of `tokens`.
+const fn match_token(bytes: &[u8], at: usize, tokens: &[&[u8]]) -> Option<usize> {
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if starts_with(bytes, at, tokens[i]) {
+            return Some(tokens[i].len());
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+const fn expect(bytes: &[u8], at: usize, c: u8) -> Result<usize, ParseSddlError> {
+    if at < bytes.len() && bytes[at] == c {
+        Ok(at + 1)
+    } else {
+        Err(ParseSddlError)
+    }
+}
+
+/// Parses an ACE of the form `(A;;<rights>;;;<sid>)` and returns the offset after it.
+const fn parse_ace(bytes: &[u8], at: usize) -> Result<usize, ParseSddlError> {
+    let mut i = match expect(bytes, at, b'(') {
+        Ok(i) => i,
+        Err(e) => return Err(e),
+    };
+
+    // Only access-allowed ACEs without ACE flags are supported.
+    if !starts_with(bytes, i, b"A;;") {
+        return Err(ParseSddlError);
+    }
+
+    i += 3;
+
+    i = match parse_rights(bytes, i) {
+        Ok(i) => i,
+        Err(e) => return Err(e),
+    };
+
+    // The rights are followed by the empty object and inherited object GUIDs.
+    if !starts_with(bytes, i, b";;;") {
+        return Err(ParseSddlError);
+    }
+
+    i += 3;
+
+    i = match parse_sid(bytes, i) {
+        Ok(i) => i,
+        Err(e) => return Err(e),
+    };
+
+    expect(bytes, i, b')')
+}
+
+/// Parses the access rights of an ACE, either as a hexadecimal mask or as a sequence of rights.
+const fn parse_rights(bytes: &[u8], at: usize) -> Result<usize, ParseSddlError> {
+    if starts_with(bytes, at, b"0x") || starts_with(bytes, at, b"0X") {
+        let mut i = at + 2;
+
+        while i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+            i += 1;
+        }
+
+        return if i == at + 2 || i - at - 2 > 8 {
+            Err(ParseSddlError)
+        } else {
+            Ok(i)
+        };
+    }
+
+    let mut i = at;
+
+    while i < bytes.len() && bytes[i] != b';' {
+        i += match match_token(bytes, i, RIGHTS) {
+            Some(len) => len,
+            None => return Err(ParseSddlError),
+        };
+    }
+
+    if i == at {
+        Err(ParseSddlError)
+    } else {
+        Ok(i)
+    }
+}
+
+/// Parses the trustee of an ACE, either a well-known SID alias or a SID of the form
+/// `S-1-<authority>(-<subauthority>)+`.
+const fn parse_sid(bytes: &[u8], at: usize) -> Result<usize, ParseSddlError> {
+    if !starts_with(bytes, at, b"S-1-") {
+        return match match_token(bytes, at, SID_ALIASES) {
+            Some(len) => Ok(at + len),
+            None => Err(ParseSddlError),
+        };
+    }
+
+    let mut i = at + 4;
+    let mut groups = 0;
+
+    loop {
+        let start = i;
+
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+
+        if i == start {
+            return Err(ParseSddlError);
+        }
+
+        groups += 1;
+
+        if i < bytes.len() && bytes[i] == b'-' {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+
+    // The identifier authority has to be followed by at least one subauthority.
+    if groups < 2 {
+        Err(ParseSddlError)
+    } else {
+        Ok(i)
+    }
+}
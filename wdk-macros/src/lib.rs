@@ -0,0 +1,99 @@
+//! Derive macros for the `wdk` crates.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr, Path};
+
+/// Derives `Pod` for a struct, after checking at compile time that it is `#[repr(C)]` or
+/// `#[repr(transparent)]`, that all of its fields are `Pod` and that it has no padding.
+///
+/// The trait is referred to as `::wdk_ioctl::Pod`, which can be changed with
+/// `#[pod(crate = "path")]`.
+#[proc_macro_derive(Pod, attributes(pod))]
+pub fn derive_pod(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_pod(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_pod(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "Pod cannot be derived for generic types",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            Fields::Unnamed(fields) => fields.unnamed.iter().collect::<Vec<_>>(),
+            Fields::Unit => Vec::new(),
+        },
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "Pod can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut has_layout = false;
+    let mut krate: Path = syn::parse_quote!(::wdk_ioctl);
+
+    for attr in &input.attrs {
+        if attr.path().is_ident("repr") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                    has_layout = true;
+                } else if meta.input.peek(syn::token::Paren) {
+                    // e.g. `packed(2)` or `align(8)`
+                    let _ = meta.input.parse::<proc_macro2::Group>()?;
+                }
+
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("pod") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("crate") {
+                    krate = meta.value()?.parse::<LitStr>()?.parse()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported pod attribute"))
+                }
+            })?;
+        }
+    }
+
+    if !has_layout {
+        return Err(Error::new(
+            Span::call_site(),
+            "Pod requires #[repr(C)] or #[repr(transparent)]",
+        ));
+    }
+
+    let types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+
+    Ok(quote! {
+        const _: () = {
+            fn assert_pod<T: #krate::Pod>() {}
+
+            fn assert_fields() {
+                #(assert_pod::<#types>();)*
+            }
+
+            assert!(
+                ::core::mem::size_of::<#name>() == 0 #(+ ::core::mem::size_of::<#types>())*,
+                "Pod types cannot have padding",
+            );
+        };
+
+        unsafe impl #krate::Pod for #name {}
+    })
+}
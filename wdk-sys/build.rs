@@ -26,6 +26,7 @@ fn generate_ntoskrnl() {
     println!("cargo:rerun-if-changed=src/wrapper.h");
     println!("cargo:rerun-if-changed=src/wrapper.c");
     println!("cargo:rustc-link-lib=ntoskrnl");
+    println!("cargo:rustc-link-lib=wdmsec");
 
     let include_dir = get_km_dir(DirectoryType::Include).unwrap();
 
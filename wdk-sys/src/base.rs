@@ -7,6 +7,7 @@ pub use cty::*;
 pub use crate::bind::base::*;
 
 pub const STATUS_SUCCESS: NTSTATUS = 0x00000000;
+pub const STATUS_PENDING: NTSTATUS = 0x00000103;
 pub const STATUS_GUARD_PAGE_VIOLATION: NTSTATUS = 0x80000001 as u32 as i32;
 pub const STATUS_DATATYPE_MISALIGNMENT: NTSTATUS = 0x80000002 as u32 as i32;
 pub const STATUS_BREAKPOINT: NTSTATUS = 0x80000003 as u32 as i32;
@@ -19,13 +20,19 @@ pub const STATUS_ACCESS_VIOLATION: NTSTATUS = 0xC0000005 as u32 as i32;
 pub const STATUS_IN_PAGE_ERROR: NTSTATUS = 0xC0000006 as u32 as i32;
 pub const STATUS_INVALID_HANDLE: NTSTATUS = 0xC0000008 as u32 as i32;
 pub const STATUS_INVALID_PARAMETER: NTSTATUS = 0xC000000D as u32 as i32;
+pub const STATUS_NO_SUCH_DEVICE: NTSTATUS = 0xC000000E as u32 as i32;
+pub const STATUS_INVALID_DEVICE_REQUEST: NTSTATUS = 0xC0000010 as u32 as i32;
 pub const STATUS_END_OF_FILE: NTSTATUS = 0xC0000011 as u32 as i32;
+pub const STATUS_MORE_PROCESSING_REQUIRED: NTSTATUS = 0xC0000016 as u32 as i32;
 pub const STATUS_NO_MEMORY: NTSTATUS = 0xC0000017 as u32 as i32;
 pub const STATUS_ILLEGAL_INSTRUCTION: NTSTATUS = 0xC000001D as u32 as i32;
+pub const STATUS_ACCESS_DENIED: NTSTATUS = 0xC0000022 as u32 as i32;
+pub const STATUS_BUFFER_TOO_SMALL: NTSTATUS = 0xC0000023 as u32 as i32;
 pub const STATUS_NONCONTINUABLE_EXCEPTION: NTSTATUS = 0xC0000025 as u32 as i32;
 pub const STATUS_INVALID_DISPOSITION: NTSTATUS = 0xC0000026 as u32 as i32;
 pub const STATUS_OBJECT_NAME_COLLISION: NTSTATUS = 0xC0000035 as u32 as i32;
 pub const STATUS_OBJECT_PATH_NOT_FOUND: NTSTATUS = 0xC000003A as u32 as i32;
+pub const STATUS_DELETE_PENDING: NTSTATUS = 0xC0000056 as u32 as i32;
 pub const STATUS_ARRAY_BOUNDS_EXCEEDED: NTSTATUS = 0xC000008C as u32 as i32;
 pub const STATUS_FLOAT_DENORMAL_OPERAND: NTSTATUS = 0xC000008D as u32 as i32;
 pub const STATUS_FLOAT_DIVIDE_BY_ZERO: NTSTATUS = 0xC000008E as u32 as i32;
@@ -40,3 +47,4 @@ pub const STATUS_PRIVILEGED_INSTRUCTION: NTSTATUS = 0xC0000096 as u32 as i32;
 pub const STATUS_INSUFFICIENT_RESOURCES: NTSTATUS = 0xC000009A as u32 as i32;
 pub const STATUS_INVALID_USER_BUFFER: NTSTATUS = 0xC00000E8 as u32 as i32;
 pub const STATUS_STACK_OVERFLOW: NTSTATUS = 0xC00000FD as u32 as i32;
+pub const STATUS_CANCELLED: NTSTATUS = 0xC0000120 as u32 as i32;
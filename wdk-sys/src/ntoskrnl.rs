@@ -11,6 +11,8 @@ extern "C" {
     pub fn _ExReleasePushLockShared(push_lock: PEX_PUSH_LOCK);
     pub fn _IoGetCurrentIrpStackLocation(irp: PIRP) -> PIO_STACK_LOCATION;
     pub fn _IoGetNextIrpStackLocation(irp: PIRP) -> PIO_STACK_LOCATION;
+    pub fn _IoSkipCurrentIrpStackLocation(irp: PIRP);
+    pub fn _IoCopyCurrentIrpStackLocationToNext(irp: PIRP);
     pub fn _IoSetCompletionRoutine(
         irp: PIRP,
         completion_routine: PIO_COMPLETION_ROUTINE,
@@ -20,26 +22,84 @@ extern "C" {
         invoke_on_cancel: BOOLEAN,
     );
     pub fn _IoCompleteRequest(irp: PIRP, priority_boost: CCHAR);
+    pub fn _IoMarkIrpPending(irp: PIRP);
+    pub fn _IoInitializeRemoveLock(
+        lock: PIO_REMOVE_LOCK,
+        tag: ULONG,
+        max_locked_minutes: ULONG,
+        high_watermark: ULONG,
+    );
+    pub fn _IoAcquireRemoveLock(lock: PIO_REMOVE_LOCK, tag: PVOID) -> NTSTATUS;
+    pub fn _IoReleaseRemoveLock(lock: PIO_REMOVE_LOCK, tag: PVOID);
+    pub fn _IoReleaseRemoveLockAndWait(lock: PIO_REMOVE_LOCK, tag: PVOID);
+    pub fn _KeAcquireSpinLock(spin_lock: PKSPIN_LOCK, old_irql: PKIRQL);
     pub fn _MmGetMdlByteCount(mdl: PMDL) -> ULONG;
     pub fn _MmGetMdlByteOffset(mdl: PMDL) -> ULONG;
+    pub fn _MmGetMdlVirtualAddress(mdl: PMDL) -> PVOID;
     pub fn _MmGetSystemAddressForMdlSafe(mdl: PMDL, priority: ULONG) -> PVOID;
+    pub fn _MmPrepareMdlForReuse(mdl: PMDL);
+    pub fn _MmProbeAndLockPagesGuarded(
+        mdl: PMDL,
+        access_mode: KPROCESSOR_MODE,
+        operation: LOCK_OPERATION,
+    ) -> NTSTATUS;
+    pub fn _MmMapLockedPagesSpecifyCacheGuarded(
+        mdl: PMDL,
+        access_mode: KPROCESSOR_MODE,
+        cache_type: MEMORY_CACHING_TYPE,
+        requested_address: PVOID,
+        bug_check_on_failure: ULONG,
+        priority: ULONG,
+        address: *mut PVOID,
+    ) -> NTSTATUS;
+    pub fn _ProbeForReadGuarded(
+        address: *const cty::c_void,
+        length: SIZE_T,
+        alignment: ULONG,
+    ) -> NTSTATUS;
+    pub fn _ProbeForWriteGuarded(
+        address: *mut cty::c_void,
+        length: SIZE_T,
+        alignment: ULONG,
+    ) -> NTSTATUS;
+    pub fn _CopyMemoryGuarded(
+        destination: *mut cty::c_void,
+        source: *const cty::c_void,
+        length: SIZE_T,
+    ) -> NTSTATUS;
     pub fn _ObDereferenceObject(p: *mut cty::c_void);
     pub fn _ObReferenceObject(p: *mut cty::c_void);
 }
 
+pub use self::_CopyMemoryGuarded as CopyMemoryGuarded;
 pub use self::_ExAcquirePushLockExclusive as ExAcquirePushLockExclusive;
 pub use self::_ExAcquirePushLockShared as ExAcquirePushLockShared;
 pub use self::_ExInitializeFastMutex as ExInitializeFastMutex;
 pub use self::_ExReleasePushLockExclusive as ExReleasePushLockExclusive;
 pub use self::_ExReleasePushLockShared as ExReleasePushLockShared;
+pub use self::_IoAcquireRemoveLock as IoAcquireRemoveLock;
 pub use self::_IoCompleteRequest as IoCompleteRequest;
+pub use self::_IoCopyCurrentIrpStackLocationToNext as IoCopyCurrentIrpStackLocationToNext;
 pub use self::_IoGetCurrentIrpStackLocation as IoGetCurrentIrpStackLocation;
 pub use self::_IoGetNextIrpStackLocation as IoGetNextIrpStackLocation;
+pub use self::_IoInitializeRemoveLock as IoInitializeRemoveLock;
+pub use self::_IoMarkIrpPending as IoMarkIrpPending;
+pub use self::_IoReleaseRemoveLock as IoReleaseRemoveLock;
+pub use self::_IoReleaseRemoveLockAndWait as IoReleaseRemoveLockAndWait;
 pub use self::_IoSetCompletionRoutine as IoSetCompletionRoutine;
+pub use self::_IoSkipCurrentIrpStackLocation as IoSkipCurrentIrpStackLocation;
+pub use self::_KeAcquireSpinLock as KeAcquireSpinLock;
 pub use self::_MmGetMdlByteCount as MmGetMdlByteCount;
 pub use self::_MmGetMdlByteOffset as MmGetMdlByteOffset;
+pub use self::_MmGetMdlVirtualAddress as MmGetMdlVirtualAddress;
 pub use self::_MmGetSystemAddressForMdlSafe as MmGetSystemAddressForMdlSafe;
+pub use self::_MmMapLockedPagesSpecifyCacheGuarded as MmMapLockedPagesSpecifyCacheGuarded;
+pub use self::_MmPrepareMdlForReuse as MmPrepareMdlForReuse;
+pub use self::_MmProbeAndLockPagesGuarded as MmProbeAndLockPagesGuarded;
 pub use self::_ObDereferenceObject as ObDereferenceObject;
 pub use self::_ObReferenceObject as ObReferenceObject;
+pub use self::_ProbeForReadGuarded as ProbeForReadGuarded;
+pub use self::_ProbeForWriteGuarded as ProbeForWriteGuarded;
 
 pub use self::IoGetCurrentProcess as PsGetCurrentProcess;
+pub use self::IofCallDriver as IoCallDriver;
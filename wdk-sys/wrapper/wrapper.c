@@ -39,6 +39,14 @@ PIO_STACK_LOCATION _IoGetNextIrpStackLocation(PIRP irp) {
     return IoGetNextIrpStackLocation(irp);
 }
 
+void _IoSkipCurrentIrpStackLocation(PIRP irp) {
+    IoSkipCurrentIrpStackLocation(irp);
+}
+
+void _IoCopyCurrentIrpStackLocationToNext(PIRP irp) {
+    IoCopyCurrentIrpStackLocationToNext(irp);
+}
+
 void _IoSetCompletionRoutine(
         PIRP irp,
         PIO_COMPLETION_ROUTINE completion_routine,
@@ -57,6 +65,35 @@ void _IoCompleteRequest(
     IoCompleteRequest(irp, priority_boost);
 }
 
+void _IoMarkIrpPending(PIRP irp) {
+    IoMarkIrpPending(irp);
+}
+
+void _IoInitializeRemoveLock(
+        PIO_REMOVE_LOCK lock,
+        ULONG tag,
+        ULONG max_locked_minutes,
+        ULONG high_watermark
+) {
+    IoInitializeRemoveLock(lock, tag, max_locked_minutes, high_watermark);
+}
+
+NTSTATUS _IoAcquireRemoveLock(PIO_REMOVE_LOCK lock, PVOID tag) {
+    return IoAcquireRemoveLock(lock, tag);
+}
+
+void _IoReleaseRemoveLock(PIO_REMOVE_LOCK lock, PVOID tag) {
+    IoReleaseRemoveLock(lock, tag);
+}
+
+void _IoReleaseRemoveLockAndWait(PIO_REMOVE_LOCK lock, PVOID tag) {
+    IoReleaseRemoveLockAndWait(lock, tag);
+}
+
+void _KeAcquireSpinLock(PKSPIN_LOCK spin_lock, PKIRQL old_irql) {
+    KeAcquireSpinLock(spin_lock, old_irql);
+}
+
 ULONG _MmGetMdlByteCount(PMDL mdl) {
     return MmGetMdlByteCount(mdl);
 }
@@ -65,9 +102,76 @@ ULONG _MmGetMdlByteOffset(PMDL mdl) {
     return MmGetMdlByteOffset(mdl);
 }
 
-//PVOID _MmGetSystemAddressForMdlSafe(PMDL mdl, ULONG priority) {
-//    return MmGetSystemAddressForMdlSafe(mdl, priority);
-//}
+PVOID _MmGetMdlVirtualAddress(PMDL mdl) {
+    return MmGetMdlVirtualAddress(mdl);
+}
+
+PVOID _MmGetSystemAddressForMdlSafe(PMDL mdl, ULONG priority) {
+    return MmGetSystemAddressForMdlSafe(mdl, priority);
+}
+
+void _MmPrepareMdlForReuse(PMDL mdl) {
+    MmPrepareMdlForReuse(mdl);
+}
+
+NTSTATUS _MmProbeAndLockPagesGuarded(PMDL mdl, KPROCESSOR_MODE access_mode, LOCK_OPERATION operation) {
+    __try {
+        MmProbeAndLockPages(mdl, access_mode, operation);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return GetExceptionCode();
+    }
+
+    return STATUS_SUCCESS;
+}
+
+NTSTATUS _MmMapLockedPagesSpecifyCacheGuarded(
+    PMDL mdl,
+    KPROCESSOR_MODE access_mode,
+    MEMORY_CACHING_TYPE cache_type,
+    PVOID requested_address,
+    ULONG bug_check_on_failure,
+    ULONG priority,
+    PVOID *address
+) {
+    __try {
+        *address = MmMapLockedPagesSpecifyCache(
+            mdl, access_mode, cache_type, requested_address, bug_check_on_failure, priority);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return GetExceptionCode();
+    }
+
+    return STATUS_SUCCESS;
+}
+
+NTSTATUS _ProbeForReadGuarded(const volatile VOID *address, SIZE_T length, ULONG alignment) {
+    __try {
+        ProbeForRead(address, length, alignment);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return GetExceptionCode();
+    }
+
+    return STATUS_SUCCESS;
+}
+
+NTSTATUS _ProbeForWriteGuarded(volatile VOID *address, SIZE_T length, ULONG alignment) {
+    __try {
+        ProbeForWrite(address, length, alignment);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return GetExceptionCode();
+    }
+
+    return STATUS_SUCCESS;
+}
+
+NTSTATUS _CopyMemoryGuarded(PVOID destination, const VOID *source, SIZE_T length) {
+    __try {
+        RtlCopyMemory(destination, source, length);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return GetExceptionCode();
+    }
+
+    return STATUS_SUCCESS;
+}
 
 void _ObDereferenceObject(PVOID p) {
     ObDereferenceObject(p);
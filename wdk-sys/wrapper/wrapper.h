@@ -71,3 +71,4 @@ extern PSERVICE_DESCRIPTOR_TABLE KeServiceDescriptorTable;
 extern USHORT *NtBuildNumber;
 
 #include "ntifs.h"
+#include "wdmsec.h"
@@ -14,21 +14,50 @@ impl<T> Pool<T> {
     pub fn new(data: T, type_: POOL_TYPE, tag: u32) -> Option<Self> {
         let layout = Layout::new::<T>();
         unsafe {
-            let ptr = ExAllocatePoolWithTag(type_, layout.size() as _, tag);
+            let ptr = ExAllocatePoolWithTag(type_, layout.size().max(1) as _, tag);
             if ptr.is_null() {
                 None
             } else {
-                let mut ptr = NonNull::<T>::new(ptr as *mut T).unwrap();
-                *(ptr.as_mut()) = data;
+                let ptr = NonNull::<T>::new(ptr as *mut T).unwrap();
+                ptr.as_ptr().write(data);
                 Some(Pool { tag, data: ptr })
             }
         }
     }
+
+    /// Consumes the [`Pool`], returning a raw pointer to the data. The pointer can be turned back
+    /// into a [`Pool`] with [`Pool::from_raw`] using the same tag.
+    pub fn into_raw(self) -> *mut T {
+        let ptr = self.data.as_ptr();
+        core::mem::forget(self);
+        ptr
+    }
+
+    /// Constructs a [`Pool`] from a raw pointer that was returned by [`Pool::into_raw`].
+    pub unsafe fn from_raw(ptr: *mut T, tag: u32) -> Self {
+        Pool {
+            tag,
+            data: NonNull::new_unchecked(ptr),
+        }
+    }
+
+    /// Consumes the [`Pool`], returning the data and freeing the allocation.
+    pub fn into_inner(self) -> T {
+        unsafe {
+            let data = self.data.as_ptr().read();
+            ExFreePoolWithTag(self.data.as_ptr() as _, self.tag);
+            core::mem::forget(self);
+            data
+        }
+    }
 }
 
 impl<T> Drop for Pool<T> {
     fn drop(&mut self) {
-        unsafe { ExFreePoolWithTag(self.data.as_ptr() as _, self.tag) }
+        unsafe {
+            core::ptr::drop_in_place(self.data.as_ptr());
+            ExFreePoolWithTag(self.data.as_ptr() as _, self.tag)
+        }
     }
 }
 
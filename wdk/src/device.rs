@@ -1,16 +1,41 @@
 use alloc::boxed::Box;
 use bitflags::bitflags;
+use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicI32, AtomicPtr, AtomicU32, Ordering};
+use fallible_collections::FallibleBox;
 
-use wdk_sys::base::{DEVICE_OBJECT, IRP, NTSTATUS, STATUS_SUCCESS};
 use wdk_sys::base::{
-    IRP_MJ_CLEANUP, IRP_MJ_CLOSE, IRP_MJ_CREATE, IRP_MJ_DEVICE_CONTROL, IRP_MJ_READ, IRP_MJ_WRITE,
+    DEVICE_OBJECT, FILE_OBJECT, IO_REMOVE_LOCK, IRP, IRP_MJ_PNP, IRP_MN_REMOVE_DEVICE, NTSTATUS,
+    POWER_SYSTEM_MAXIMUM, PVOID, STATUS_PENDING, STATUS_SUCCESS, _MODE as MODE,
+};
+use wdk_sys::base::{
+    IRP_MJ_CLEANUP, IRP_MJ_CLOSE, IRP_MJ_CREATE, IRP_MJ_DEVICE_CONTROL, IRP_MJ_DIRECTORY_CONTROL,
+    IRP_MJ_FILE_SYSTEM_CONTROL, IRP_MJ_FLUSH_BUFFERS, IRP_MJ_INTERNAL_DEVICE_CONTROL,
+    IRP_MJ_LOCK_CONTROL, IRP_MJ_QUERY_INFORMATION, IRP_MJ_QUERY_VOLUME_INFORMATION, IRP_MJ_READ,
+    IRP_MJ_SET_INFORMATION, IRP_MJ_SHUTDOWN, IRP_MJ_WRITE,
+};
+use wdk_sys::ntoskrnl::{
+    IoAcquireRemoveLock, IoDeleteDevice, IoDetachDevice, IoGetCurrentIrpStackLocation,
+    IoReleaseRemoveLock, IoReleaseRemoveLockAndWait,
 };
-use wdk_sys::ntoskrnl::{IoDeleteDevice, IoGetCurrentIrpStackLocation};
 
-use crate::error::Error;
-use crate::request::{IoControlRequest, IoRequest, ReadRequest, WriteRequest};
+use crate::error::{Error, IntoResult};
+use crate::ioctl::ControlCode;
+use crate::pnp::PnpState;
+use crate::request::{
+    DirectoryControlRequest, FileSystemControlRequest, FlushBuffersRequest,
+    InternalIoControlRequest, IoControlRequest, IoRequest, LockControlRequest, PendingToken,
+    PriorityBoost, QueryInformationRequest, QueryVolumeInformationRequest, ReadRequest,
+    RequestStatus, SetInformationRequest, ShutdownRequest, WriteRequest,
+};
+use crate::symbolic_link::SymbolicLink;
+
+/// The offset of the extra bytes that were reserved in the device extension, see
+/// [`Device::extra_extension`].
+pub(crate) const EXTRA_EXTENSION_OFFSET: usize =
+    (core::mem::size_of::<DeviceExtension>() + 15) & !15;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Access {
@@ -30,6 +55,11 @@ impl Access {
 bitflags! {
     pub struct DeviceFlags: u32 {
         const SECURE_OPEN = wdk_sys::base::FILE_DEVICE_SECURE_OPEN;
+        const AUTOGENERATED_DEVICE_NAME = wdk_sys::base::FILE_AUTOGENERATED_DEVICE_NAME;
+        const REMOVABLE_MEDIA = wdk_sys::base::FILE_REMOVABLE_MEDIA;
+        const READ_ONLY_DEVICE = wdk_sys::base::FILE_READ_ONLY_DEVICE;
+        const REMOTE_DEVICE = wdk_sys::base::FILE_REMOTE_DEVICE;
+        const CHARACTERISTIC_PNP_DEVICE = wdk_sys::base::FILE_CHARACTERISTIC_PNP_DEVICE;
     }
 }
 
@@ -37,6 +67,7 @@ bitflags! {
     pub struct DeviceDoFlags: u32 {
         const DO_BUFFERED_IO = wdk_sys::base::DO_BUFFERED_IO;
         const DO_DIRECT_IO   = wdk_sys::base::DO_DIRECT_IO;
+        const DO_POWER_PAGABLE = wdk_sys::base::DO_POWER_PAGABLE;
     }
 }
 
@@ -100,11 +131,15 @@ pub enum DeviceType {
     VirtualDisk,
     WaveIn,
     WaveOut,
+    /// A device type that is not covered by the other variants, e.g. a vendor-defined device type
+    /// in the range `0x8000..=0xFFFF`. The conversions never produce a custom device type with
+    /// the value of a predefined one, so device types can be compared directly.
+    Custom(u16),
 }
 
-impl Into<u32> for DeviceType {
-    fn into(self) -> u32 {
-        match self {
+impl From<DeviceType> for u32 {
+    fn from(value: DeviceType) -> Self {
+        match value {
             DeviceType::Port8042 => wdk_sys::base::FILE_DEVICE_8042_PORT,
             DeviceType::Acpi => wdk_sys::base::FILE_DEVICE_ACPI,
             DeviceType::Battery => wdk_sys::base::FILE_DEVICE_BATTERY,
@@ -163,12 +198,17 @@ impl Into<u32> for DeviceType {
             DeviceType::VirtualDisk => wdk_sys::base::FILE_DEVICE_VIRTUAL_DISK,
             DeviceType::WaveIn => wdk_sys::base::FILE_DEVICE_WAVE_IN,
             DeviceType::WaveOut => wdk_sys::base::FILE_DEVICE_WAVE_OUT,
+            DeviceType::Custom(value) => u32::from(value),
         }
     }
 }
 
 impl From<u32> for DeviceType {
+    /// Converts the device type of a device object. Device types are 16 bits wide, the upper bits
+    /// are ignored.
     fn from(value: u32) -> Self {
+        let value = value & 0xFFFF;
+
         match value {
             wdk_sys::base::FILE_DEVICE_8042_PORT => DeviceType::Port8042,
             wdk_sys::base::FILE_DEVICE_ACPI => DeviceType::Acpi,
@@ -228,21 +268,61 @@ impl From<u32> for DeviceType {
             wdk_sys::base::FILE_DEVICE_VIRTUAL_DISK => DeviceType::VirtualDisk,
             wdk_sys::base::FILE_DEVICE_WAVE_IN => DeviceType::WaveIn,
             wdk_sys::base::FILE_DEVICE_WAVE_OUT => DeviceType::WaveOut,
-            _ => DeviceType::Unknown,
+            _ => DeviceType::Custom(value as u16),
+        }
+    }
+}
+
+impl From<u16> for DeviceType {
+    fn from(value: u16) -> Self {
+        DeviceType::from(u32::from(value))
+    }
+}
+
+/// How the device type of a control code is checked before a device control request is passed on
+/// to [`DeviceOperations::ioctl`]. Requests that fail the check are completed with
+/// `STATUS_INVALID_PARAMETER`.
+#[derive(Copy, Clone, Debug)]
+pub enum DeviceTypeCheck {
+    /// The control code has to be defined for the device type of the device.
+    Strict,
+    /// The control code has to be defined for the device type of the device or one of the given
+    /// device types.
+    Allow(&'static [DeviceType]),
+    /// The device type of the control code is not checked.
+    Disabled,
+}
+
+impl DeviceTypeCheck {
+    pub fn accepts(&self, device: DeviceType, code: ControlCode) -> bool {
+        let code = DeviceType::from(code.device_type());
+
+        match *self {
+            DeviceTypeCheck::Strict => code == device,
+            DeviceTypeCheck::Allow(types) => code == device || types.contains(&code),
+            DeviceTypeCheck::Disabled => true,
         }
     }
 }
 
 #[repr(C)]
 pub struct Operations {
-    dispatch: Option<extern "C" fn(*mut DEVICE_OBJECT, *mut IRP, u8) -> NTSTATUS>,
-    release: Option<extern "C" fn(*mut DEVICE_OBJECT)>,
+    pub(crate) dispatch: Option<extern "C" fn(*mut DEVICE_OBJECT, *mut IRP, u8) -> NTSTATUS>,
+    pub(crate) release: Option<extern "C" fn(*mut DEVICE_OBJECT)>,
+    /// Releases the [`FileState`] in the `FsContext` of a file object that was opened on the
+    /// device, or `None` if the `FsContext` of the file objects does not hold one.
+    pub(crate) release_file_state: Option<unsafe fn(*mut FILE_OBJECT)>,
+    /// See [`DeviceOperations::UNCHECKED_ACCESS`].
+    pub(crate) unchecked_access: &'static [ControlCode],
 }
 
 pub struct Device {
     raw: *mut DEVICE_OBJECT,
 }
 
+// The device data is `Send + Sync`, so the device can be kept alive and deleted on any thread.
+unsafe impl Send for Device {}
+
 impl Device {
     pub unsafe fn from_raw(raw: *mut DEVICE_OBJECT) -> Self {
         Self { raw }
@@ -260,28 +340,151 @@ impl Device {
         core::mem::replace(&mut self.raw, core::ptr::null_mut())
     }
 
+    /// Returns the device extension. The fields that change while requests are being dispatched
+    /// are atomics or cells, so the extension is only ever handed out as a shared reference.
     pub fn extension(&self) -> &DeviceExtension {
-        unsafe { &*((*self.raw).DeviceExtension as *const DeviceExtension) }
+        unsafe { &*self.extension_ptr() }
     }
 
-    pub fn extension_mut(&self) -> &mut DeviceExtension {
-        unsafe { &mut *((*self.raw).DeviceExtension as *mut DeviceExtension) }
+    /// Returns a pointer to the device extension, which is used to set it up before the device
+    /// becomes visible and to tear it down once the device is no longer reachable.
+    pub(crate) fn extension_ptr(&self) -> *mut DeviceExtension {
+        unsafe { (*self.raw).DeviceExtension as *mut DeviceExtension }
     }
 
     pub fn device_type(&self) -> DeviceType {
         self.extension().device_type
     }
 
+    /// Returns the device that this device is attached to, if any.
+    pub fn lower_device(&self) -> Option<LowerDevice> {
+        let lower = self.extension().lower_device.load(Ordering::Acquire);
+
+        if lower.is_null() {
+            None
+        } else {
+            Some(unsafe { LowerDevice::from_raw(lower) })
+        }
+    }
+
+    pub fn pnp_state(&self) -> PnpState {
+        PnpState::from(self.extension().pnp_state.load(Ordering::Relaxed))
+    }
+
     pub fn vtable(&self) -> &Operations {
         unsafe { &*(self.extension().vtable as *const _) }
     }
 
-    pub fn data<T: DeviceOperations>(&self) -> &T {
+    /// Acquires the remove lock of the device for the given tag, which fails once the device is
+    /// being deleted.
+    pub(crate) fn acquire_remove_lock(&self, tag: PVOID) -> Result<(), Error> {
+        unsafe { IoAcquireRemoveLock(self.extension().remove_lock.get(), tag) }.into_result()
+    }
+
+    pub(crate) fn release_remove_lock(&self, tag: PVOID) {
+        unsafe { IoReleaseRemoveLock(self.extension().remove_lock.get(), tag) }
+    }
+
+    /// Releases the remove lock that was acquired for the given tag and waits until every other
+    /// acquisition has been released. The remove lock cannot be acquired anymore afterwards.
+    pub(crate) fn release_remove_lock_and_wait(&self, tag: PVOID) {
+        unsafe { IoReleaseRemoveLockAndWait(self.extension().remove_lock.get(), tag) }
+    }
+
+    /// Returns the extra bytes that were reserved in the device extension through
+    /// [`DeviceBuilder::extension_size`], which are zeroed and 16-byte aligned.
+    ///
+    /// [`DeviceBuilder::extension_size`]: crate::driver::DeviceBuilder::extension_size
+    pub fn extra_extension(&self) -> *mut u8 {
+        unsafe { ((*self.raw).DeviceExtension as *mut u8).add(EXTRA_EXTENSION_OFFSET) }
+    }
+
+    pub fn extra_extension_size(&self) -> usize {
+        self.extension().extra_size
+    }
+
+    /// Returns the device data. The I/O manager dispatches requests concurrently, so the data is
+    /// only ever handed out as a shared reference.
+    pub fn data<T: Sync>(&self) -> &T {
         unsafe { &*(self.extension().data as *const T) }
     }
+}
+
+/// A reference on the remove lock of the device that a request was dispatched to, tagged with the
+/// IRP. It keeps the device from being deleted until the request is completed, and is released
+/// when dropped.
+pub(crate) struct RemoveLockGuard {
+    device: *mut DEVICE_OBJECT,
+    tag: PVOID,
+}
+
+unsafe impl Send for RemoveLockGuard {}
+
+impl RemoveLockGuard {
+    /// Acquires the remove lock of the device that owns the current stack location of the
+    /// request, which fails with `STATUS_DELETE_PENDING` once the device is being deleted.
+    pub(crate) fn acquire(request: &IoRequest) -> Result<Self, Error> {
+        let guard = unsafe { Self::adopt(request) };
+        let device = unsafe { Device::from_raw(guard.device) };
+
+        let result = device.acquire_remove_lock(guard.tag);
+        device.into_raw();
+
+        match result {
+            Ok(()) => Ok(guard),
+            Err(e) => {
+                core::mem::forget(guard);
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes over a reference that was acquired for the request before and given up through
+    /// [`core::mem::forget`], e.g. while the request is parked in an [`IrpQueue`].
+    ///
+    /// [`IrpQueue`]: crate::request::IrpQueue
+    pub(crate) unsafe fn adopt(request: &IoRequest) -> Self {
+        Self {
+            device: request.stack_location().DeviceObject,
+            tag: request.irp() as *const IRP as PVOID,
+        }
+    }
+}
+
+impl Drop for RemoveLockGuard {
+    fn drop(&mut self) {
+        let device = unsafe { Device::from_raw(self.device) };
+
+        device.release_remove_lock(self.tag);
+        device.into_raw();
+    }
+}
+
+/// A non-owning handle to the device that a device is attached to, i.e. the next lower device in
+/// the device stack that requests are passed on to.
+#[derive(Copy, Clone)]
+pub struct LowerDevice {
+    raw: *mut DEVICE_OBJECT,
+}
+
+unsafe impl Send for LowerDevice {}
+unsafe impl Sync for LowerDevice {}
+
+impl LowerDevice {
+    pub unsafe fn from_raw(raw: *mut DEVICE_OBJECT) -> Self {
+        Self { raw }
+    }
 
-    pub fn data_mut<T: DeviceOperations>(&self) -> &mut T {
-        unsafe { &mut *(self.extension().data as *mut T) }
+    pub unsafe fn as_raw(&self) -> *const DEVICE_OBJECT {
+        self.raw as *const _
+    }
+
+    pub unsafe fn as_raw_mut(&self) -> *mut DEVICE_OBJECT {
+        self.raw
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        unsafe { (*self.raw).DeviceType.into() }
     }
 }
 
@@ -291,11 +494,26 @@ impl Drop for Device {
             return;
         }
 
+        // Stop new handles from being opened through the symbolic link.
+        unsafe {
+            (*self.extension_ptr()).symbolic_link.take();
+        }
+
+        // Wait for the requests that are still being dispatched, unless this already happened
+        // while handling IRP_MN_REMOVE_DEVICE.
+        if self.acquire_remove_lock(null_mut()).is_ok() {
+            self.release_remove_lock_and_wait(null_mut());
+        }
+
         unsafe {
             if let Some(release) = self.vtable().release {
                 release(self.raw);
             }
 
+            if let Some(lower) = self.lower_device() {
+                IoDetachDevice(lower.as_raw_mut());
+            }
+
             IoDeleteDevice(self.raw);
         }
     }
@@ -304,92 +522,414 @@ impl Drop for Device {
 pub struct RequestError(pub Error, pub IoRequest);
 
 pub enum Completion {
-    Complete(u32, IoRequest),
+    /// The request is completed successfully, reporting the given value as `Information`.
+    Complete(usize, IoRequest),
+    /// The request is completed with the given status, e.g. a warning that still reports data,
+    /// and the given priority boost.
+    CompleteWith(RequestStatus, PriorityBoost, IoRequest),
+    /// The request has been marked pending through [`PendingRequest::new`] or
+    /// [`IrpQueue::insert`], which hand out the token, and will be completed later. The dispatch
+    /// routine returns `STATUS_PENDING` without touching the IRP again.
+    ///
+    /// [`PendingRequest::new`]: crate::request::PendingRequest::new
+    /// [`IrpQueue::insert`]: crate::request::IrpQueue::insert
+    Pending(PendingToken),
+    /// The request has been passed on to a lower device, which owns it now. The dispatch routine
+    /// returns the given status, as returned by `IoCallDriver`.
+    Forwarded(NTSTATUS),
 }
 
-pub trait DeviceOperations: Sync + Sized {
-    fn create(&mut self, _device: &Device, request: IoRequest) -> Result<Completion, RequestError> {
+/// The request handlers of a device.
+///
+/// The I/O manager dispatches requests for a device on whatever thread issued them, so handlers
+/// run concurrently and only get shared access to the device data and file contexts. State that
+/// is modified has to be protected by one of the locks in [`crate::sync`] or by atomics.
+pub trait DeviceOperations: Send + Sync + Sized {
+    /// The state that is kept for every open handle to the device. It is set by
+    /// [`DeviceOperations::create`], stored in the `FsContext` of the file object, handed to the
+    /// requests that are issued on the handle and dropped after [`DeviceOperations::close`].
+    ///
+    /// Requests that carry no file object, such as the ones that other drivers build, or that
+    /// were issued on a handle without a context, get `None` instead.
+    type FileContext: Send + Sync = ();
+
+    /// The check of the device type of the control codes that are passed to
+    /// [`DeviceOperations::ioctl`].
+    const DEVICE_TYPE_CHECK: DeviceTypeCheck = DeviceTypeCheck::Strict;
+
+    /// The control codes whose required access is not checked against the access that was granted
+    /// to the handle. Any other device control request whose control code requires read or write
+    /// access fails with `STATUS_ACCESS_DENIED` unless the handle was opened with that access.
+    const UNCHECKED_ACCESS: &'static [ControlCode] = &[];
+
+    /// Called when a handle to the device is opened. The context of the handle is set through
+    /// `context`, which already lives in the `FsContext` of the file object, so it has to be set
+    /// before the request is completed or pended. If the create is completed with a status other
+    /// than a success, now or after returning [`Completion::Pending`], the context is dropped
+    /// again, as the handle is never opened.
+    fn create(
+        &self,
+        _device: &Device,
+        _context: &mut Option<Self::FileContext>,
+        request: IoRequest,
+    ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request))
     }
 
-    fn close(&mut self, _device: &Device, request: IoRequest) -> Result<Completion, RequestError> {
+    fn close(
+        &self,
+        _device: &Device,
+        _file: Option<&Self::FileContext>,
+        request: IoRequest,
+    ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request))
     }
 
     fn cleanup(
-        &mut self,
+        &self,
         _device: &Device,
+        _file: Option<&Self::FileContext>,
         request: IoRequest,
     ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request))
     }
 
-    fn read(&mut self, _device: &Device, request: ReadRequest) -> Result<Completion, RequestError> {
+    fn read(
+        &self,
+        _device: &Device,
+        _file: Option<&Self::FileContext>,
+        request: ReadRequest,
+    ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request.into()))
     }
 
     fn write(
-        &mut self,
+        &self,
         _device: &Device,
+        _file: Option<&Self::FileContext>,
         request: WriteRequest,
     ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request.into()))
     }
 
     fn ioctl(
-        &mut self,
+        &self,
         _device: &Device,
+        _file: Option<&Self::FileContext>,
         request: IoControlRequest,
     ) -> Result<Completion, RequestError> {
         Ok(Completion::Complete(0, request.into()))
     }
+
+    fn query_information(
+        &self,
+        _device: &Device,
+        request: QueryInformationRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn set_information(
+        &self,
+        _device: &Device,
+        request: SetInformationRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn flush_buffers(
+        &self,
+        _device: &Device,
+        request: FlushBuffersRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Complete(0, request.into()))
+    }
+
+    /// Only called for devices registered through `IoRegisterShutdownNotification`.
+    fn shutdown(
+        &self,
+        _device: &Device,
+        request: ShutdownRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Complete(0, request.into()))
+    }
+
+    fn internal_ioctl(
+        &self,
+        _device: &Device,
+        request: InternalIoControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn fs_control(
+        &self,
+        _device: &Device,
+        request: FileSystemControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn lock_control(
+        &self,
+        _device: &Device,
+        request: LockControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn directory_control(
+        &self,
+        _device: &Device,
+        request: DirectoryControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    fn query_volume_information(
+        &self,
+        _device: &Device,
+        request: QueryVolumeInformationRequest,
+    ) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request.into()))
+    }
+
+    /// Called for any major function that has no dedicated handler.
+    fn other(&self, _device: &Device, request: IoRequest) -> Result<Completion, RequestError> {
+        Err(RequestError(Error::INVALID_DEVICE_REQUEST, request))
+    }
 }
 
-extern "C" fn dispatch_callback<T: DeviceOperations>(
+pub(crate) extern "C" fn dispatch_callback<T: DeviceOperations>(
     device: *mut DEVICE_OBJECT,
     irp: *mut IRP,
     major: u8,
 ) -> NTSTATUS {
     let device = unsafe { Device::from_raw(device) };
-    let data: &mut T = device.data_mut();
+    let data: &T = device.data();
     let request = unsafe { IoRequest::from_raw(irp) };
 
     let result = match major as _ {
-        IRP_MJ_CREATE => data.create(&device, request),
-        IRP_MJ_CLOSE => data.close(&device, request),
-        IRP_MJ_CLEANUP => data.cleanup(&device, request),
-        IRP_MJ_READ => {
-            let read_request = ReadRequest { inner: request };
+        IRP_MJ_CREATE => {
+            let file = FileState {
+                granted_access: granted_access(&request),
+                context: None,
+            };
+
+            // The context is in place before the handler can pend the request, as the file object
+            // must not be touched once the request may have been completed.
+            match unsafe { set_file_state(request.file_object(), file) } {
+                Ok(file) => data.create(&device, &mut file.context, request),
+                Err(e) => Err(RequestError(e, request)),
+            }
+        }
+        IRP_MJ_CLOSE | IRP_MJ_CLEANUP | IRP_MJ_READ | IRP_MJ_WRITE | IRP_MJ_DEVICE_CONTROL => {
+            let file = unsafe { file_context::<FileState<T::FileContext>>(request.file_object()) };
 
-            data.read(&device, read_request)
+            dispatch_file(data, &device, file, major, request)
+        }
+        IRP_MJ_QUERY_INFORMATION => {
+            data.query_information(&device, QueryInformationRequest { inner: request })
+        }
+        IRP_MJ_SET_INFORMATION => {
+            data.set_information(&device, SetInformationRequest { inner: request })
+        }
+        IRP_MJ_FLUSH_BUFFERS => data.flush_buffers(&device, FlushBuffersRequest { inner: request }),
+        IRP_MJ_SHUTDOWN => data.shutdown(&device, ShutdownRequest { inner: request }),
+        IRP_MJ_INTERNAL_DEVICE_CONTROL => {
+            data.internal_ioctl(&device, InternalIoControlRequest { inner: request })
+        }
+        IRP_MJ_FILE_SYSTEM_CONTROL => {
+            data.fs_control(&device, FileSystemControlRequest { inner: request })
+        }
+        IRP_MJ_LOCK_CONTROL => data.lock_control(&device, LockControlRequest { inner: request }),
+        IRP_MJ_DIRECTORY_CONTROL => {
+            data.directory_control(&device, DirectoryControlRequest { inner: request })
         }
-        IRP_MJ_WRITE => {
-            let write_request = WriteRequest { inner: request };
+        IRP_MJ_QUERY_VOLUME_INFORMATION => {
+            data.query_volume_information(&device, QueryVolumeInformationRequest { inner: request })
+        }
+        _ => data.other(&device, request),
+    };
+
+    device.into_raw();
+
+    finish_dispatch(result)
+}
 
-            data.write(&device, write_request)
+fn dispatch_file<T: DeviceOperations>(
+    data: &T,
+    device: &Device,
+    file: Option<&FileState<T::FileContext>>,
+    major: u8,
+    request: IoRequest,
+) -> Result<Completion, RequestError> {
+    let context = file.and_then(|file| file.context.as_ref());
+
+    match major as _ {
+        IRP_MJ_CLOSE => {
+            let file_object = request.file_object();
+            let result = data.close(device, context, request);
+
+            // The handle is gone, so its context can be released.
+            unsafe { release_file_context::<FileState<T::FileContext>>(file_object) };
+
+            result
         }
-        IRP_MJ_DEVICE_CONTROL => {
+        IRP_MJ_CLEANUP => data.cleanup(device, context, request),
+        IRP_MJ_READ => data.read(device, context, ReadRequest { inner: request }),
+        IRP_MJ_WRITE => data.write(device, context, WriteRequest { inner: request }),
+        _ => {
             let control_request = IoControlRequest { inner: request };
 
-            if device.device_type() == control_request.control_code().device_type() {
-                data.ioctl(&device, control_request)
-            } else {
+            let code = control_request.control_code();
+
+            if !T::DEVICE_TYPE_CHECK.accepts(device.device_type(), code) {
                 Err(RequestError(
                     Error::INVALID_PARAMETER,
                     control_request.into(),
                 ))
+            } else if !T::UNCHECKED_ACCESS.contains(&code)
+                && !has_access(file.map(|file| file.granted_access), &control_request, code)
+            {
+                Err(RequestError(Error::ACCESS_DENIED, control_request.into()))
+            } else {
+                data.ioctl(device, context, control_request)
             }
         }
-        _ => Err(RequestError(Error::INVALID_PARAMETER, request)),
+    }
+}
+
+/// What is stored in the `FsContext` of a file object that was opened on the device. The granted
+/// access comes first, such that it can be read without knowing the type of the context.
+#[repr(C)]
+struct FileState<C> {
+    /// The access that was granted to the handle when it was opened.
+    granted_access: u32,
+    context: Option<C>,
+}
+
+/// Returns whether the request may use the control code, given the access that was granted to
+/// its handle. The bits of [`RequiredAccess`] match `FILE_READ_DATA` and `FILE_WRITE_DATA`.
+///
+/// Requests without a file state were not issued through a handle that was opened on the device,
+/// so only kernel-mode requests are trusted with control codes that require access.
+///
+/// [`RequiredAccess`]: crate::ioctl::RequiredAccess
+fn has_access(granted_access: Option<u32>, request: &IoRequest, code: ControlCode) -> bool {
+    let required = code.required_access().bits();
+
+    match granted_access {
+        Some(granted_access) => granted_access & required == required,
+        None => required == 0 || request.irp().RequestorMode as MODE::Type == MODE::KernelMode,
+    }
+}
+
+/// Returns whether the device control request may use the control code, checked the same way as
+/// before the request is passed to [`DeviceOperations::ioctl`]. This is what
+/// [`IoctlRouter`] checks before it calls a handler.
+///
+/// [`IoctlRouter`]: crate::ioctl::IoctlRouter
+pub(crate) fn request_has_access(request: &IoRequest, code: ControlCode) -> bool {
+    let device = unsafe { Device::from_raw(request.stack_location().DeviceObject) };
+    let vtable = device.vtable();
+
+    let result = vtable.unchecked_access.contains(&code) || {
+        let granted_access = if vtable.release_file_state.is_some() {
+            unsafe { file_context::<u32>(request.file_object()) }.copied()
+        } else {
+            None
+        };
+
+        has_access(granted_access, request, code)
     };
 
     device.into_raw();
 
+    result
+}
+
+/// Returns the access that was granted to the handle that a create request opens.
+fn granted_access(request: &IoRequest) -> u32 {
+    unsafe {
+        let security_context = request.stack_location().Parameters.Create.SecurityContext;
+
+        if security_context.is_null() || (*security_context).AccessState.is_null() {
+            return 0;
+        }
+
+        (*(*security_context).AccessState).PreviouslyGrantedAccess
+    }
+}
+
+/// Stores the state of a handle that is being opened in the `FsContext` of its file object.
+unsafe fn set_file_state<'a, C>(
+    file_object: *mut FILE_OBJECT,
+    file: FileState<C>,
+) -> Result<&'a mut FileState<C>, Error> {
+    if file_object.is_null() {
+        return Err(Error::INVALID_PARAMETER);
+    }
+
+    let file =
+        <Box<_> as FallibleBox<_>>::try_new(file).map_err(|_| Error::INSUFFICIENT_RESOURCES)?;
+    let file = Box::into_raw(file);
+
+    (*file_object).FsContext = file as PVOID;
+
+    Ok(&mut *file)
+}
+
+/// Releases the file state of a create request that is completed with a status other than a
+/// success, as the handle is never opened and no `IRP_MJ_CLOSE` follows.
+pub(crate) fn release_failed_create(request: &IoRequest) {
+    let file_object = request.file_object();
+    let device_object = request.stack_location().DeviceObject;
+
+    if request.major() as u32 != IRP_MJ_CREATE || file_object.is_null() || device_object.is_null() {
+        return;
+    }
+
+    let device = unsafe { Device::from_raw(device_object) };
+
+    if let Some(release) = device.vtable().release_file_state {
+        unsafe { release(file_object) };
+    }
+
+    device.into_raw();
+}
+
+unsafe fn file_context<'a, C>(file_object: *mut FILE_OBJECT) -> Option<&'a C> {
+    if file_object.is_null() || (*file_object).FsContext.is_null() {
+        None
+    } else {
+        Some(&*((*file_object).FsContext as *const C))
+    }
+}
+
+unsafe fn release_file_context<C>(file_object: *mut FILE_OBJECT) {
+    let ptr = core::mem::replace(&mut (*file_object).FsContext, null_mut());
+
+    if !ptr.is_null() {
+        let _ = Box::from_raw(ptr as *mut C);
+    }
+}
+
+/// Completes the request of a dispatch routine according to the handler's result, and returns
+/// the status that the dispatch routine should return.
+pub(crate) fn finish_dispatch(result: Result<Completion, RequestError>) -> NTSTATUS {
     match result {
         Ok(Completion::Complete(size, request)) => {
             request.complete(Ok(size));
             STATUS_SUCCESS
         }
+        Ok(Completion::CompleteWith(status, boost, request)) => {
+            let ntstatus = status.ntstatus();
+            request.complete_with_boost(status, boost);
+            ntstatus
+        }
+        Ok(Completion::Pending(_)) => STATUS_PENDING,
+        Ok(Completion::Forwarded(status)) => status,
         Err(RequestError(e, request)) => {
             let status = e.to_ntstatus();
             request.complete(Err(e));
@@ -398,7 +938,7 @@ extern "C" fn dispatch_callback<T: DeviceOperations>(
     }
 }
 
-extern "C" fn release_callback<T: DeviceOperations>(device: *mut DEVICE_OBJECT) {
+pub(crate) extern "C" fn release_callback<T>(device: *mut DEVICE_OBJECT) {
     unsafe {
         let extension = (*device).DeviceExtension as *mut DeviceExtension;
 
@@ -413,6 +953,8 @@ impl<T: DeviceOperations> DeviceOperationsVtable<T> {
     pub const VTABLE: Operations = Operations {
         dispatch: Some(dispatch_callback::<T>),
         release: Some(release_callback::<T>),
+        release_file_state: Some(release_file_context::<FileState<T::FileContext>>),
+        unchecked_access: T::UNCHECKED_ACCESS,
     };
 }
 
@@ -421,6 +963,22 @@ pub struct DeviceExtension {
     pub vtable: *const Operations,
     pub data: *mut cty::c_void,
     pub device_type: DeviceType,
+    pub lower_device: AtomicPtr<DEVICE_OBJECT>,
+    pub physical_device: *mut DEVICE_OBJECT,
+    /// The [`PnpState`] of the device.
+    pub pnp_state: AtomicU32,
+    pub previous_pnp_state: AtomicU32,
+    /// The [`DevicePowerState`] of the device.
+    ///
+    /// [`DevicePowerState`]: crate::power::DevicePowerState
+    pub device_power_state: AtomicI32,
+    /// The deepest device power state that the device can be in for every system power state, as
+    /// reported by `IRP_MN_QUERY_CAPABILITIES` when the device was started.
+    pub device_states: [AtomicI32; POWER_SYSTEM_MAXIMUM as usize],
+    pub idle_counter: AtomicPtr<u32>,
+    pub remove_lock: UnsafeCell<IO_REMOVE_LOCK>,
+    pub symbolic_link: Option<SymbolicLink>,
+    pub extra_size: usize,
 }
 
 pub extern "C" fn dispatch_device(device: *mut DEVICE_OBJECT, irp: *mut IRP) -> NTSTATUS {
@@ -428,11 +986,34 @@ pub extern "C" fn dispatch_device(device: *mut DEVICE_OBJECT, irp: *mut IRP) ->
     let device = unsafe { Device::from_raw(device) };
     let vtable = device.vtable();
 
-    match vtable.dispatch {
-        Some(dispatch) => dispatch(device.into_raw(), irp, stack_location.MajorFunction),
-        _ => {
-            device.into_raw();
-            STATUS_SUCCESS
-        }
+    // Keep the device from being deleted while the request is being dispatched.
+    if let Err(e) = device.acquire_remove_lock(irp as PVOID) {
+        device.into_raw();
+
+        let status = e.to_ntstatus();
+        unsafe { IoRequest::from_raw(irp) }.complete(Err(e));
+        return status;
+    }
+
+    // The handler of IRP_MN_REMOVE_DEVICE releases the remove lock itself before it deletes the
+    // device.
+    let removing = stack_location.MajorFunction as u32 == IRP_MJ_PNP
+        && stack_location.MinorFunction as u32 == IRP_MN_REMOVE_DEVICE;
+
+    let status = match vtable.dispatch {
+        Some(dispatch) => dispatch(
+            unsafe { device.as_raw_mut() },
+            irp,
+            stack_location.MajorFunction,
+        ),
+        _ => STATUS_SUCCESS,
+    };
+
+    if !removing {
+        device.release_remove_lock(irp as PVOID);
     }
+
+    device.into_raw();
+
+    status
 }
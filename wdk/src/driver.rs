@@ -1,16 +1,33 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
 use core::mem::size_of;
 use core::ptr::null_mut;
-use fallible_collections::FallibleBox;
+use core::sync::atomic::{AtomicI32, AtomicPtr, AtomicU32};
+use fallible_collections::{FallibleBox, FallibleVec};
 
-use wdk_sys::base::{DRIVER_OBJECT, UNICODE_STRING};
-use wdk_sys::ntoskrnl::IoCreateDevice;
+use wdk_sys::base::{
+    DO_DEVICE_INITIALIZING, DRIVER_OBJECT, GUID, PDRIVER_UNLOAD, PVOID, UNICODE_STRING,
+};
+use wdk_sys::ntoskrnl::{
+    IoAllocateDriverObjectExtension, IoCreateDevice, IoCreateDeviceSecure,
+    IoGetDriverObjectExtension, IoInitializeRemoveLock,
+};
 
 use crate::device::{
     Access, Device, DeviceDoFlags, DeviceExtension, DeviceFlags, DeviceOperations,
-    DeviceOperationsVtable, DeviceType,
+    DeviceOperationsVtable, DeviceType, Operations, EXTRA_EXTENSION_OFFSET,
 };
 use crate::error::{Error, IntoResult};
+use crate::guid::{Guid, GuidExt};
+use crate::pnp::PnpState;
+use crate::power::DevicePowerState;
+use crate::sddl::Sddl;
+use crate::string::UnicodeString;
+use crate::symbolic_link::SymbolicLink;
+use crate::sync::FastMutex;
+
+const REMOVE_LOCK_TAG: u32 = u32::from_ne_bytes(*b"rrml");
 
 pub struct Driver {
     pub raw: *mut DRIVER_OBJECT,
@@ -29,6 +46,11 @@ impl Driver {
         self.raw as _
     }
 
+    /// Returns a builder for a new device of this driver.
+    pub fn device_builder(&mut self) -> DeviceBuilder {
+        DeviceBuilder::new(self)
+    }
+
     pub fn create_device<T>(
         &mut self,
         name: &mut UNICODE_STRING,
@@ -41,36 +63,413 @@ impl Driver {
     where
         T: DeviceOperations,
     {
+        self.device_builder()
+            .raw_name(name)
+            .device_type(device_type)
+            .characteristics(device_flags)
+            .do_flags(device_do_flags)
+            .exclusive(access.is_exclusive())
+            .build(data)
+    }
+
+    /// Creates a device with the security descriptor described by `sddl`, through
+    /// `IoCreateDeviceSecure`. Administrators can override the security descriptor for every
+    /// device of the device setup class `class` through the registry.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_device_secure<T>(
+        &mut self,
+        name: &mut UNICODE_STRING,
+        device_type: DeviceType,
+        device_flags: DeviceFlags,
+        device_do_flags: DeviceDoFlags,
+        access: Access,
+        sddl: Sddl,
+        class: &Guid,
+        data: T,
+    ) -> Result<Device, Error>
+    where
+        T: DeviceOperations,
+    {
+        self.device_builder()
+            .raw_name(name)
+            .device_type(device_type)
+            .characteristics(device_flags)
+            .do_flags(device_do_flags)
+            .exclusive(access.is_exclusive())
+            .security(sddl, class)
+            .build(data)
+    }
+
+    /// Keeps the device alive until the driver is unloaded.
+    pub fn register_device(&mut self, device: Device) -> Result<(), Error> {
+        self.register_resource(device)
+    }
+
+    /// Keeps the symbolic link alive until the driver is unloaded.
+    pub fn register_symbolic_link(&mut self, link: SymbolicLink) -> Result<(), Error> {
+        self.register_resource(link)
+    }
+
+    /// Keeps the resource alive until the driver is unloaded, e.g. a registered callback, a system
+    /// thread or a timer whose `Drop` implementation tears it down again.
+    ///
+    /// The registered resources are dropped in the reverse order of their registration by the
+    /// `DriverUnload` routine that `DriverEntry` installs, after the `DriverUnload` routine that
+    /// was set in [`KernelModule::init`], if any. If the resource cannot be registered, it is
+    /// dropped right away.
+    ///
+    /// [`KernelModule::init`]: crate::module::KernelModule::init
+    pub fn register_resource<R>(&mut self, resource: R) -> Result<(), Error>
+    where
+        R: Send + 'static,
+    {
+        let resource: Box<dyn Send> = <Box<_> as FallibleBox<_>>::try_new(resource)?;
+        let resources = self.resources()?;
+
+        resources
+            .resources
+            .lock()
+            .ok_or(Error::UNSUCCESSFUL)?
+            .try_push(resource)?;
+
+        Ok(())
+    }
+
+    /// Returns the resources of the driver, which are stored in the driver object extension.
+    fn resources(&mut self) -> Result<&DriverResources, Error> {
+        let resources = unsafe { IoGetDriverObjectExtension(self.raw, resources_id()) }
+            as *const DriverResources;
+
+        // The resources are created by `DriverEntry` before any code of the driver runs.
+        unsafe { resources.as_ref() }.ok_or(Error::UNSUCCESSFUL)
+    }
+}
+
+/// The identifier of the driver object extension that holds the registered resources.
+static RESOURCES_ID: u8 = 0;
+
+fn resources_id() -> PVOID {
+    &RESOURCES_ID as *const u8 as PVOID
+}
+
+struct DriverResources {
+    resources: FastMutex<Vec<Box<dyn Send>>>,
+    unload: PDRIVER_UNLOAD,
+}
+
+/// Creates the resources of the driver in the driver object extension. This is called by
+/// `DriverEntry` before any code of the driver runs, so registering resources never races with
+/// their creation.
+pub(crate) unsafe fn init_resources(driver: *mut DRIVER_OBJECT) -> Result<(), Error> {
+    let list = FastMutex::new(Vec::new())?;
+    let mut resources = null_mut();
+
+    IoAllocateDriverObjectExtension(
+        driver,
+        resources_id(),
+        size_of::<DriverResources>() as u32,
+        &mut resources,
+    )
+    .into_result()?;
+
+    (resources as *mut DriverResources).write(DriverResources {
+        resources: list,
+        unload: None,
+    });
+
+    Ok(())
+}
+
+/// Installs the `DriverUnload` routine that drops the registered resources once `DriverEntry` has
+/// initialized the driver. A `DriverUnload` routine that the driver has set itself is chained, and
+/// called before the resources are dropped.
+pub(crate) unsafe fn install_unload(driver: *mut DRIVER_OBJECT) {
+    let resources = IoGetDriverObjectExtension(driver, resources_id()) as *mut DriverResources;
+
+    if resources.is_null() {
+        return;
+    }
+
+    (*resources).unload = (*driver).DriverUnload.take();
+    (*driver).DriverUnload = Some(driver_unload);
+}
+
+unsafe extern "C" fn driver_unload(driver: *mut DRIVER_OBJECT) {
+    let resources = IoGetDriverObjectExtension(driver, resources_id()) as *mut DriverResources;
+
+    if resources.is_null() {
+        return;
+    }
+
+    if let Some(unload) = (*resources).unload {
+        unload(driver);
+    }
+
+    release_resources(driver);
+}
+
+/// Drops the registered resources of the driver in the reverse order of their registration. This
+/// may only be called once the driver goes away, i.e. when it is unloaded or when `DriverEntry`
+/// fails, as the I/O manager does not call `DriverUnload` in the latter case.
+pub(crate) unsafe fn release_resources(driver: *mut DRIVER_OBJECT) {
+    let resources = IoGetDriverObjectExtension(driver, resources_id()) as *mut DriverResources;
+
+    if resources.is_null() {
+        return;
+    }
+
+    // The I/O manager frees the driver object extension itself after the driver is gone.
+    let DriverResources { resources, .. } = resources.read();
+    let mut resources = resources.into_inner();
+
+    while let Some(resource) = resources.pop() {
+        drop(resource);
+    }
+}
+
+enum DeviceName<'a> {
+    None,
+    Name(&'a str),
+    Raw(*mut UNICODE_STRING),
+}
+
+/// A builder for devices, created through [`Driver::device_builder`].
+///
+/// ```ignore
+/// let device = driver
+///     .device_builder()
+///     .name("\\Device\\Example")
+///     .symbolic_link("\\??\\Example")
+///     .device_type(DeviceType::Unknown)
+///     .buffered_io()
+///     .security(SDDL_DEVOBJ_SYS_ALL_ADM_ALL, &GUID_DEVCLASS_EXAMPLE)
+///     .build(MyDevice::default())?;
+/// ```
+pub struct DeviceBuilder<'a> {
+    driver: &'a mut Driver,
+    name: DeviceName<'a>,
+    symbolic_link: Option<&'a str>,
+    device_type: DeviceType,
+    characteristics: DeviceFlags,
+    do_flags: DeviceDoFlags,
+    exclusive: bool,
+    security: Option<(Sddl<'a>, &'a Guid)>,
+    extension_size: usize,
+}
+
+impl<'a> DeviceBuilder<'a> {
+    pub fn new(driver: &'a mut Driver) -> Self {
+        Self {
+            driver,
+            name: DeviceName::None,
+            symbolic_link: None,
+            device_type: DeviceType::Unknown,
+            characteristics: DeviceFlags::SECURE_OPEN,
+            do_flags: DeviceDoFlags::empty(),
+            exclusive: false,
+            security: None,
+            extension_size: 0,
+        }
+    }
+
+    /// Sets the name of the device, e.g. `\Device\Example`.
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = DeviceName::Name(name);
+        self
+    }
+
+    pub(crate) fn raw_name(mut self, name: *mut UNICODE_STRING) -> Self {
+        self.name = DeviceName::Raw(name);
+        self
+    }
+
+    /// Lets the I/O manager generate a name for the device.
+    pub fn auto_generated_name(mut self) -> Self {
+        self.name = DeviceName::None;
+        self.characteristics |= DeviceFlags::AUTOGENERATED_DEVICE_NAME;
+        self
+    }
+
+    /// Creates a symbolic link to the device, e.g. `\??\Example`, which is owned by the device.
+    pub fn symbolic_link(mut self, name: &'a str) -> Self {
+        self.symbolic_link = Some(name);
+        self
+    }
+
+    pub fn device_type(mut self, device_type: DeviceType) -> Self {
+        self.device_type = device_type;
+        self
+    }
+
+    /// Sets the device characteristics, [`DeviceFlags::SECURE_OPEN`] by default.
+    pub fn characteristics(mut self, characteristics: DeviceFlags) -> Self {
+        self.characteristics = characteristics;
+        self
+    }
+
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub fn do_flags(mut self, do_flags: DeviceDoFlags) -> Self {
+        self.do_flags = do_flags;
+        self
+    }
+
+    pub fn buffered_io(mut self) -> Self {
+        self.do_flags.remove(DeviceDoFlags::DO_DIRECT_IO);
+        self.do_flags |= DeviceDoFlags::DO_BUFFERED_IO;
+        self
+    }
+
+    pub fn direct_io(mut self) -> Self {
+        self.do_flags.remove(DeviceDoFlags::DO_BUFFERED_IO);
+        self.do_flags |= DeviceDoFlags::DO_DIRECT_IO;
+        self
+    }
+
+    /// Creates the device through `IoCreateDeviceSecure` with the security descriptor described
+    /// by `sddl`. Administrators can override the security descriptor for every device of the
+    /// device setup class `class` through the registry.
+    pub fn security(mut self, sddl: Sddl<'a>, class: &'a Guid) -> Self {
+        self.security = Some((sddl, class));
+        self
+    }
+
+    /// Reserves extra bytes in the device extension, see [`Device::extra_extension`].
+    pub fn extension_size(mut self, size: usize) -> Self {
+        self.extension_size = size;
+        self
+    }
+
+    /// Creates the device with `T` as its device data. The device is ready to receive requests
+    /// once this returns. If any step fails, everything that was set up is torn down again.
+    pub fn build<T>(self, data: T) -> Result<Device, Error>
+    where
+        T: DeviceOperations,
+    {
+        let device = self.build_with_vtable(data, &DeviceOperationsVtable::<T>::VTABLE)?;
+
+        unsafe {
+            (*device.as_raw_mut()).Flags &= !DO_DEVICE_INITIALIZING;
+        }
+
+        Ok(device)
+    }
+
+    /// Creates the device without clearing `DO_DEVICE_INITIALIZING`, which is left to the caller
+    /// once it has finished setting up the device.
+    pub(crate) fn build_with_vtable<T>(
+        self,
+        data: T,
+        vtable: &'static Operations,
+    ) -> Result<Device, Error>
+    where
+        T: Send + Sync,
+    {
+        let extension_size = EXTRA_EXTENSION_OFFSET + self.extension_size;
+
+        if extension_size > u32::MAX as usize {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
         // Box the data.
         let data = <Box<_> as FallibleBox<_>>::try_new(data)?;
 
+        let owned_name = match self.name {
+            DeviceName::Name(name) => Some(UnicodeString::from_str(name)?),
+            _ => None,
+        };
+        let mut name_string = owned_name.as_ref().map(|name| name.to_unicode_string());
+        let name = match self.name {
+            DeviceName::Raw(name) => name,
+            _ => name_string
+                .as_mut()
+                .map_or(null_mut(), |name| name as *mut _),
+        };
+
+        // A symbolic link needs a name to point to.
+        if self.symbolic_link.is_some() && name.is_null() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
         // Create the device.
         let mut device = null_mut();
 
-        unsafe {
-            IoCreateDevice(
-                self.raw,
-                size_of::<DeviceExtension>() as u32,
-                name,
-                device_type.into(),
-                device_flags.bits(),
-                access.is_exclusive() as _,
-                &mut device,
-            )
+        match self.security {
+            Some((sddl, class)) => {
+                let sddl = UnicodeString::from_str(sddl.as_str())?;
+                let class: GUID = class.to_raw();
+
+                unsafe {
+                    IoCreateDeviceSecure(
+                        self.driver.raw,
+                        extension_size as u32,
+                        name,
+                        self.device_type.into(),
+                        self.characteristics.bits(),
+                        self.exclusive as _,
+                        &sddl.to_unicode_string(),
+                        &class,
+                        &mut device,
+                    )
+                }
+            }
+            None => unsafe {
+                IoCreateDevice(
+                    self.driver.raw,
+                    extension_size as u32,
+                    name,
+                    self.device_type.into(),
+                    self.characteristics.bits(),
+                    self.exclusive as _,
+                    &mut device,
+                )
+            },
         }
         .into_result()?;
 
         unsafe {
-            (*device).Flags |= device_do_flags.bits();
+            (*device).Flags |= self.do_flags.bits();
         }
 
         let device = unsafe { Device::from_raw(device) };
 
-        // Store the boxed data and vtable.
-        let extension = device.extension_mut();
-        extension.device_type = device_type;
-        extension.vtable = &DeviceOperationsVtable::<T>::VTABLE;
-        extension.data = Box::into_raw(data) as *mut cty::c_void;
+        // Store the boxed data and vtable. Nothing else can reach the device before it is set up,
+        // so the extension is written through its pointer.
+        let extension = device.extension_ptr();
+
+        unsafe {
+            extension.write(DeviceExtension {
+                vtable,
+                data: Box::into_raw(data) as *mut cty::c_void,
+                device_type: self.device_type,
+                lower_device: AtomicPtr::new(null_mut()),
+                physical_device: null_mut(),
+                pnp_state: AtomicU32::new(PnpState::NotStarted as u32),
+                previous_pnp_state: AtomicU32::new(PnpState::NotStarted as u32),
+                device_power_state: AtomicI32::new(DevicePowerState::D0.into()),
+                device_states: Default::default(),
+                idle_counter: AtomicPtr::new(null_mut()),
+                remove_lock: UnsafeCell::new(core::mem::zeroed()),
+                symbolic_link: None,
+                extra_size: self.extension_size,
+            });
+
+            IoInitializeRemoveLock((*extension).remove_lock.get(), REMOVE_LOCK_TAG, 0, 0);
+        }
+
+        // From here on, dropping the device on failure deletes the device again.
+        if let Some(link) = self.symbolic_link {
+            let link = UnicodeString::from_str(link)?;
+            let target = unsafe { UnicodeString::from_unicode_string(&*name)? };
+            let link = SymbolicLink::new(link, &target)?;
+
+            unsafe {
+                (*extension).symbolic_link = Some(link);
+            }
+        }
 
         Ok(device)
     }
@@ -1,16 +1,19 @@
 use fallible_collections::TryReserveError;
+use wdk_ioctl::{ParseGuidError, ParseSddlError};
 use wdk_sys::base::NTSTATUS;
 use wdk_sys::base::{
-    STATUS_ACCESS_VIOLATION, STATUS_ARRAY_BOUNDS_EXCEEDED, STATUS_BREAKPOINT,
-    STATUS_DATATYPE_MISALIGNMENT, STATUS_END_OF_FILE, STATUS_FLOAT_DENORMAL_OPERAND,
+    STATUS_ACCESS_DENIED, STATUS_ACCESS_VIOLATION, STATUS_ARRAY_BOUNDS_EXCEEDED, STATUS_BREAKPOINT,
+    STATUS_BUFFER_TOO_SMALL, STATUS_CANCELLED, STATUS_DATATYPE_MISALIGNMENT, STATUS_DELETE_PENDING,
+    STATUS_DEVICE_NOT_READY, STATUS_END_OF_FILE, STATUS_FLOAT_DENORMAL_OPERAND,
     STATUS_FLOAT_DIVIDE_BY_ZERO, STATUS_FLOAT_INEXACT_RESULT, STATUS_FLOAT_INVALID_OPERATION,
     STATUS_FLOAT_OVERFLOW, STATUS_FLOAT_STACK_CHECK, STATUS_FLOAT_UNDERFLOW,
     STATUS_GUARD_PAGE_VIOLATION, STATUS_ILLEGAL_INSTRUCTION, STATUS_INSUFFICIENT_RESOURCES,
-    STATUS_INTEGER_DIVIDE_BY_ZERO, STATUS_INTEGER_OVERFLOW, STATUS_INVALID_DISPOSITION,
-    STATUS_INVALID_HANDLE, STATUS_INVALID_PARAMETER, STATUS_INVALID_USER_BUFFER,
-    STATUS_IN_PAGE_ERROR, STATUS_NONCONTINUABLE_EXCEPTION, STATUS_NOT_IMPLEMENTED,
-    STATUS_NO_MEMORY, STATUS_PRIVILEGED_INSTRUCTION, STATUS_SINGLE_STEP, STATUS_STACK_OVERFLOW,
-    STATUS_SUCCESS, STATUS_UNSUCCESSFUL, STATUS_UNWIND_CONSOLIDATE,
+    STATUS_INTEGER_DIVIDE_BY_ZERO, STATUS_INTEGER_OVERFLOW, STATUS_INVALID_DEVICE_REQUEST,
+    STATUS_INVALID_DISPOSITION, STATUS_INVALID_HANDLE, STATUS_INVALID_PARAMETER,
+    STATUS_INVALID_USER_BUFFER, STATUS_IN_PAGE_ERROR, STATUS_NONCONTINUABLE_EXCEPTION,
+    STATUS_NOT_IMPLEMENTED, STATUS_NO_MEMORY, STATUS_NO_SUCH_DEVICE, STATUS_PRIVILEGED_INSTRUCTION,
+    STATUS_SINGLE_STEP, STATUS_STACK_OVERFLOW, STATUS_SUCCESS, STATUS_UNSUCCESSFUL,
+    STATUS_UNWIND_CONSOLIDATE,
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -28,6 +31,7 @@ impl Error {
     pub const IN_PAGE_ERROR: Error = Error(STATUS_IN_PAGE_ERROR);
     pub const INVALID_HANDLE: Error = Error(STATUS_INVALID_HANDLE);
     pub const INVALID_PARAMETER: Error = Error(STATUS_INVALID_PARAMETER);
+    pub const INVALID_DEVICE_REQUEST: Error = Error(STATUS_INVALID_DEVICE_REQUEST);
     pub const END_OF_FILE: Error = Error(STATUS_END_OF_FILE);
     pub const NO_MEMORY: Error = Error(STATUS_NO_MEMORY);
     pub const ILLEGAL_INSTRUCTION: Error = Error(STATUS_ILLEGAL_INSTRUCTION);
@@ -47,6 +51,12 @@ impl Error {
     pub const INSUFFICIENT_RESOURCES: Error = Error(STATUS_INSUFFICIENT_RESOURCES);
     pub const INVALID_USER_BUFFER: Error = Error(STATUS_INVALID_USER_BUFFER);
     pub const STACK_OVERFLOW: Error = Error(STATUS_STACK_OVERFLOW);
+    pub const CANCELLED: Error = Error(STATUS_CANCELLED);
+    pub const NO_SUCH_DEVICE: Error = Error(STATUS_NO_SUCH_DEVICE);
+    pub const DELETE_PENDING: Error = Error(STATUS_DELETE_PENDING);
+    pub const BUFFER_TOO_SMALL: Error = Error(STATUS_BUFFER_TOO_SMALL);
+    pub const ACCESS_DENIED: Error = Error(STATUS_ACCESS_DENIED);
+    pub const DEVICE_NOT_READY: Error = Error(STATUS_DEVICE_NOT_READY);
 
     pub fn from_ntstatus(status: NTSTATUS) -> Error {
         Error(status)
@@ -78,3 +88,15 @@ impl From<TryReserveError> for Error {
         }
     }
 }
+
+impl From<ParseGuidError> for Error {
+    fn from(_: ParseGuidError) -> Error {
+        Error(STATUS_INVALID_PARAMETER)
+    }
+}
+
+impl From<ParseSddlError> for Error {
+    fn from(_: ParseSddlError) -> Error {
+        Error(STATUS_INVALID_PARAMETER)
+    }
+}
@@ -0,0 +1,235 @@
+//! This module provides support for filter drivers: a filter device object is attached on top of
+//! an existing device stack, after which it sees every request that is sent to the stack before
+//! the devices below it. Requests are passed on to the [`LowerDevice`] unless [`FilterOperations`]
+//! decides otherwise, optionally with a completion routine to inspect or modify the result.
+//!
+//! A keyboard filter would typically implement [`FilterOperations::read`] and forward the read
+//! with [`IoRequest::forward_with_completion`] to look at the scan codes once they arrive, while
+//! disk and volume filters mostly implement [`FilterOperations::ioctl`].
+
+use core::marker::PhantomData;
+use core::ptr::null_mut;
+use core::sync::atomic::Ordering;
+
+use wdk_sys::base::{
+    DEVICE_OBJECT, DO_BUFFERED_IO, DO_DEVICE_INITIALIZING, DO_DIRECT_IO, DO_POWER_PAGABLE,
+    FILE_OBJECT, FILE_READ_ATTRIBUTES, IRP, IRP_MJ_DEVICE_CONTROL, IRP_MJ_INTERNAL_DEVICE_CONTROL,
+    IRP_MJ_PNP, IRP_MJ_POWER, IRP_MJ_READ, IRP_MJ_WRITE, IRP_MN_REMOVE_DEVICE, NTSTATUS, PVOID,
+};
+use wdk_sys::ntoskrnl::{
+    IoAttachDeviceToDeviceStackSafe, IoDetachDevice, IoGetDeviceObjectPointer, ObDereferenceObject,
+    PoStartNextPowerIrp,
+};
+
+use crate::device::{
+    finish_dispatch, release_callback, Completion, Device, DeviceFlags, DeviceType, LowerDevice,
+    Operations, RequestError,
+};
+use crate::driver::Driver;
+use crate::error::{Error, IntoResult};
+use crate::power::pass_down;
+use crate::request::{
+    InternalIoControlRequest, IoControlRequest, IoRequest, ReadRequest, WriteRequest,
+};
+use crate::string::UnicodeString;
+
+/// The device stack that a filter device is attached to.
+pub enum AttachTarget<'a> {
+    /// The device stack of the named device, e.g. `\Device\KeyboardClass0`.
+    Name(&'a UnicodeString),
+    /// The device stack of the given device object.
+    Device(*mut DEVICE_OBJECT),
+}
+
+pub trait FilterOperations: Send + Sync + Sized {
+    fn read(
+        &self,
+        _device: &Device,
+        lower: &LowerDevice,
+        request: ReadRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Forwarded(request.inner.forward(lower)))
+    }
+
+    fn write(
+        &self,
+        _device: &Device,
+        lower: &LowerDevice,
+        request: WriteRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Forwarded(request.inner.forward(lower)))
+    }
+
+    fn ioctl(
+        &self,
+        _device: &Device,
+        lower: &LowerDevice,
+        request: IoControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Forwarded(request.inner.forward(lower)))
+    }
+
+    fn internal_ioctl(
+        &self,
+        _device: &Device,
+        lower: &LowerDevice,
+        request: InternalIoControlRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Forwarded(request.inner.forward(lower)))
+    }
+
+    /// Called for any major function that has no dedicated handler, except for `IRP_MJ_POWER`
+    /// and `IRP_MN_REMOVE_DEVICE`, which are always passed on.
+    fn other(
+        &self,
+        _device: &Device,
+        lower: &LowerDevice,
+        request: IoRequest,
+    ) -> Result<Completion, RequestError> {
+        Ok(Completion::Forwarded(request.forward(lower)))
+    }
+}
+
+pub struct FilterOperationsVtable<T>(PhantomData<T>);
+
+impl<T: FilterOperations> FilterOperationsVtable<T> {
+    pub const VTABLE: Operations = Operations {
+        dispatch: Some(filter_dispatch_callback::<T>),
+        release: Some(release_callback::<T>),
+        release_file_state: None,
+        unchecked_access: &[],
+    };
+}
+
+impl Driver {
+    /// Creates a filter device with `T` as its device data and attaches it to the top of the
+    /// target device stack. The filter device takes over the device type, characteristics and
+    /// I/O flags of the device it is attached to.
+    ///
+    /// The returned device owns the filter device: dropping it detaches the filter device and
+    /// deletes it. If the device stack is removed first, the filter device is detached when
+    /// `IRP_MN_REMOVE_DEVICE` passes by, such that the stack can go away, and it fails any further
+    /// requests until the returned device is dropped.
+    pub fn attach_device<T>(&mut self, target: AttachTarget, data: T) -> Result<Device, Error>
+    where
+        T: FilterOperations,
+    {
+        match target {
+            AttachTarget::Device(target) => self.attach_device_to(target, data),
+            AttachTarget::Name(name) => {
+                let mut file_object: *mut FILE_OBJECT = null_mut();
+                let mut target = null_mut();
+
+                unsafe {
+                    IoGetDeviceObjectPointer(
+                        &mut name.to_unicode_string(),
+                        FILE_READ_ATTRIBUTES,
+                        &mut file_object,
+                        &mut target,
+                    )
+                }
+                .into_result()?;
+
+                let result = self.attach_device_to(target, data);
+
+                // The device stays referenced by the attachment.
+                unsafe {
+                    ObDereferenceObject(file_object as _);
+                }
+
+                result
+            }
+        }
+    }
+
+    fn attach_device_to<T>(&mut self, target: *mut DEVICE_OBJECT, data: T) -> Result<Device, Error>
+    where
+        T: FilterOperations,
+    {
+        let device_type = DeviceType::from(unsafe { (*target).DeviceType });
+
+        let device = self
+            .device_builder()
+            .device_type(device_type)
+            .characteristics(DeviceFlags::empty())
+            .build_with_vtable(data, &FilterOperationsVtable::<T>::VTABLE)?;
+
+        unsafe {
+            let raw = device.as_raw_mut();
+
+            (*raw).Characteristics = (*target).Characteristics;
+
+            // The lower device is stored before the attachment becomes visible, such that the
+            // dispatch routine can rely on it.
+            IoAttachDeviceToDeviceStackSafe(raw, target, device.extension().lower_device.as_ptr())
+                .into_result()?;
+
+            let lower = device.extension().lower_device.load(Ordering::Acquire);
+
+            (*raw).Flags |= (*lower).Flags & (DO_BUFFERED_IO | DO_DIRECT_IO | DO_POWER_PAGABLE);
+            (*raw).Flags &= !DO_DEVICE_INITIALIZING;
+        }
+
+        Ok(device)
+    }
+}
+
+extern "C" fn filter_dispatch_callback<T: FilterOperations>(
+    device: *mut DEVICE_OBJECT,
+    irp: *mut IRP,
+    major: u8,
+) -> NTSTATUS {
+    let device = unsafe { Device::from_raw(device) };
+    let data: &T = device.data();
+    let request = unsafe { IoRequest::from_raw(irp) };
+
+    let lower = match device.lower_device() {
+        Some(lower) => lower,
+        None => {
+            // The device stack has been removed already.
+            device.into_raw();
+            request.complete(Err(Error::DELETE_PENDING));
+            return Error::DELETE_PENDING.to_ntstatus();
+        }
+    };
+
+    let result = match major as _ {
+        IRP_MJ_READ => data.read(&device, &lower, ReadRequest { inner: request }),
+        IRP_MJ_WRITE => data.write(&device, &lower, WriteRequest { inner: request }),
+        IRP_MJ_DEVICE_CONTROL => data.ioctl(&device, &lower, IoControlRequest { inner: request }),
+        IRP_MJ_INTERNAL_DEVICE_CONTROL => {
+            data.internal_ioctl(&device, &lower, InternalIoControlRequest { inner: request })
+        }
+        IRP_MJ_POWER => {
+            unsafe { PoStartNextPowerIrp(irp) };
+            Ok(Completion::Forwarded(pass_down(request, &lower)))
+        }
+        IRP_MJ_PNP if request.minor() as u32 == IRP_MN_REMOVE_DEVICE => {
+            // Wait for the requests that are still being dispatched. The remove lock was acquired
+            // for this request when it was dispatched.
+            device.release_remove_lock_and_wait(irp as PVOID);
+
+            let status = request.forward(&lower);
+
+            // Detach from the device stack such that it can go away. The device object itself is
+            // owned by the device that `attach_device` returned and is deleted when that is
+            // dropped.
+            unsafe {
+                IoDetachDevice(lower.as_raw_mut());
+            }
+
+            device
+                .extension()
+                .lower_device
+                .store(null_mut(), Ordering::Release);
+            device.into_raw();
+
+            return status;
+        }
+        _ => data.other(&device, &lower, request),
+    };
+
+    device.into_raw();
+
+    finish_dispatch(result)
+}
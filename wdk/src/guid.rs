@@ -0,0 +1,29 @@
+//! This module provides a globally unique identifier type that can be built in `const` contexts,
+//! e.g. for device interface classes. Parsing and formatting live in `wdk-ioctl`, which does not
+//! depend on the kernel and can be shared with user-mode clients.
+
+use wdk_sys::base::GUID;
+
+pub use wdk_ioctl::{Guid, ParseGuidError};
+
+/// Conversions between [`Guid`] and the `GUID` of the kernel headers, which share their layout.
+pub trait GuidExt {
+    fn from_raw(guid: GUID) -> Self;
+
+    fn to_raw(&self) -> GUID;
+}
+
+impl GuidExt for Guid {
+    fn from_raw(guid: GUID) -> Self {
+        Self::from_fields(guid.Data1, guid.Data2, guid.Data3, guid.Data4)
+    }
+
+    fn to_raw(&self) -> GUID {
+        GUID {
+            Data1: self.data1,
+            Data2: self.data2,
+            Data3: self.data3,
+            Data4: self.data4,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! This module provides device interfaces, which make devices discoverable from user mode through
+//! the `SetupDi*` and `CM_*` APIs by their interface class GUID instead of a hard-coded name.
+
+use core::ptr::null_mut;
+
+use wdk_sys::base::{GUID, UNICODE_STRING};
+use wdk_sys::ntoskrnl::{
+    IoRegisterDeviceInterface, IoSetDeviceInterfaceState, RtlFreeUnicodeString,
+};
+
+use crate::device::Device;
+use crate::error::{Error, IntoResult};
+use crate::guid::{Guid, GuidExt};
+use crate::string::UnicodeString;
+
+/// A registered device interface. The interface is disabled again when it is dropped.
+pub struct DeviceInterface {
+    name: UnicodeString,
+    enabled: bool,
+}
+
+impl DeviceInterface {
+    /// Returns the symbolic link name of the interface, which user mode opens the device with.
+    pub fn symbolic_link_name(&self) -> &UnicodeString {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables or disables the interface. Enabling the interface creates the symbolic link and
+    /// notifies the applications that registered for arrivals of the interface class.
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        if self.enabled == enabled {
+            return Ok(());
+        }
+
+        unsafe { IoSetDeviceInterfaceState(&mut self.name.to_unicode_string(), enabled as _) }
+            .into_result()?;
+
+        self.enabled = enabled;
+
+        Ok(())
+    }
+}
+
+impl Drop for DeviceInterface {
+    fn drop(&mut self) {
+        let _ = self.set_enabled(false);
+    }
+}
+
+impl Device {
+    /// Registers a device interface of the given class for the physical device object of this
+    /// device. The interface is registered in a disabled state, see
+    /// [`DeviceInterface::set_enabled`]. This is only supported for devices that were created in
+    /// `AddDevice`.
+    pub fn register_interface(
+        &self,
+        class: &Guid,
+        reference: Option<&UnicodeString>,
+    ) -> Result<DeviceInterface, Error> {
+        let pdo = self.extension().physical_device;
+
+        if pdo.is_null() {
+            return Err(Error::INVALID_DEVICE_REQUEST);
+        }
+
+        let class: GUID = class.to_raw();
+        let mut reference = reference.map(|reference| reference.to_unicode_string());
+        let mut name: UNICODE_STRING = unsafe { core::mem::zeroed() };
+
+        unsafe {
+            IoRegisterDeviceInterface(
+                pdo,
+                &class,
+                reference
+                    .as_mut()
+                    .map_or(null_mut(), |reference| reference as *mut _),
+                &mut name,
+            )
+        }
+        .into_result()?;
+
+        // The name was allocated by the I/O manager, keep our own copy of it.
+        let result = UnicodeString::from_unicode_string(&name);
+
+        unsafe {
+            RtlFreeUnicodeString(&mut name);
+        }
+
+        Ok(DeviceInterface {
+            name: result?,
+            enabled: false,
+        })
+    }
+}
@@ -1,117 +1,120 @@
-use bitflags::bitflags;
+use core::mem::size_of;
 
-use wdk_sys::base::{
-    FILE_ANY_ACCESS, FILE_READ_DATA, FILE_WRITE_DATA, METHOD_BUFFERED, METHOD_IN_DIRECT,
-    METHOD_NEITHER, METHOD_OUT_DIRECT,
-};
+use crate::device::{request_has_access, Completion, RequestError};
+use crate::error::Error;
+use crate::request::IoControlRequest;
 
-use crate::device::DeviceType;
+pub use wdk_ioctl::{ioctl, ControlCode, Pod, RequiredAccess, TransferMethod};
+pub use wdk_ioctl::{
+    FILE_ANY_ACCESS, FILE_DEVICE_UNKNOWN, FILE_READ_DATA, FILE_WRITE_DATA, METHOD_BUFFERED,
+    METHOD_IN_DIRECT, METHOD_NEITHER, METHOD_OUT_DIRECT,
+};
 
-bitflags! {
-    pub struct RequiredAccess: u32 {
-        const ANY_ACCESS = FILE_ANY_ACCESS;
-        const READ_DATA = FILE_READ_DATA;
-        const WRITE_DATA = FILE_WRITE_DATA;
-        const READ_WRITE_DATA = FILE_READ_DATA | FILE_WRITE_DATA;
-    }
+/// Routes a device control request to the handler of its control code. Every handler takes its
+/// input by reference and returns its output, the router takes care of the buffers:
+///
+/// ```ignore
+/// fn ioctl(
+///     &self,
+///     _device: &Device,
+///     _file: Option<&Self::FileContext>,
+///     request: IoControlRequest,
+/// ) -> Result<Completion, RequestError> {
+///     IoctlRouter::new(request)
+///         .route(IOCTL_READ_VALUE, |_: &()| self.read_value())
+///         .route(IOCTL_WRITE_VALUE, |value: &u32| self.write_value(*value))
+///         .finish()
+/// }
+/// ```
+///
+/// A request is only routed to a handler if its control code matches exactly, including the
+/// required access and the transfer method. Requests that are not routed to any handler fail with
+/// `STATUS_INVALID_DEVICE_REQUEST`.
+///
+/// The required access of the control code is checked against the access that was granted to the
+/// handle, like [`DeviceOperations::UNCHECKED_ACCESS`] describes, also for requests that were
+/// queued or that a filter device received. As filter devices do not own the file objects of
+/// their device stack, they only route control codes that require access for kernel-mode
+/// requests.
+///
+/// [`DeviceOperations::UNCHECKED_ACCESS`]: crate::device::DeviceOperations::UNCHECKED_ACCESS
+pub struct IoctlRouter {
+    request: IoControlRequest,
+    code: u32,
+    result: Option<Result<usize, Error>>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-#[repr(u32)]
-pub enum TransferMethod {
-    Neither = METHOD_NEITHER,
-    InputDirect = METHOD_IN_DIRECT,
-    OutputDirect = METHOD_OUT_DIRECT,
-    Buffered = METHOD_BUFFERED,
-}
+impl IoctlRouter {
+    pub fn new(request: IoControlRequest) -> Self {
+        let code = request.control_code().raw();
 
-impl From<u32> for TransferMethod {
-    fn from(value: u32) -> Self {
-        match value & 0x3 {
-            METHOD_NEITHER => Self::Neither,
-            METHOD_IN_DIRECT => Self::InputDirect,
-            METHOD_OUT_DIRECT => Self::OutputDirect,
-            METHOD_BUFFERED => Self::Buffered,
-            _ => unreachable!(),
+        Self {
+            request,
+            code,
+            result: None,
         }
     }
-}
 
-impl Into<u32> for TransferMethod {
-    fn into(self) -> u32 {
-        match self {
-            Self::Neither => METHOD_NEITHER,
-            Self::InputDirect => METHOD_IN_DIRECT,
-            Self::OutputDirect => METHOD_OUT_DIRECT,
-            Self::Buffered => METHOD_BUFFERED,
+    /// Routes the request to `handler` if it was issued with the given control code. If the handle
+    /// lacks the access that the control code requires, the request fails with
+    /// `STATUS_ACCESS_DENIED` without calling the handler.
+    ///
+    /// The input buffer has to hold at least an `In` and the output buffer at least an `Out`,
+    /// otherwise the request fails with `STATUS_INVALID_PARAMETER` or `STATUS_BUFFER_TOO_SMALL`
+    /// respectively, without calling the handler. The output is written back to the output buffer
+    /// and its size is reported as the number of bytes that were transferred.
+    ///
+    /// Payload types have to be [`Pod`]. Drivers that do not depend on `wdk-ioctl` directly can
+    /// derive it with `#[derive(Pod)]` and `#[pod(crate = "wdk::ioctl")]`.
+    pub fn route<In, Out, F>(mut self, code: ControlCode, handler: F) -> Self
+    where
+        In: Pod,
+        Out: Pod,
+        F: FnOnce(&In) -> Result<Out, Error>,
+    {
+        if self.result.is_none() && self.code == code.raw() {
+            self.result = Some(if request_has_access(&self.request, code) {
+                dispatch(&mut self.request, handler)
+            } else {
+                Err(Error::ACCESS_DENIED)
+            });
         }
-    }
-}
-
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ControlCode(
-    pub DeviceType,
-    pub RequiredAccess,
-    pub u32,
-    pub TransferMethod,
-);
-
-impl ControlCode {
-    const METHOD_BITS: usize = 2;
-    const NUM_BITS: usize = 12;
-    const ACCESS_BITS: usize = 2;
-    const TYPE_BITS: usize = 16;
 
-    const METHOD_SHIFT: usize = 0;
-    const NUM_SHIFT: usize = Self::METHOD_SHIFT + Self::METHOD_BITS;
-    const ACCESS_SHIFT: usize = Self::NUM_SHIFT + Self::NUM_BITS;
-    const TYPE_SHIFT: usize = Self::ACCESS_SHIFT + Self::ACCESS_BITS;
-
-    const METHOD_MASK: u32 = (1 << Self::METHOD_BITS) - 1;
-    const NUM_MASK: u32 = (1 << Self::NUM_BITS) - 1;
-    const ACCESS_MASK: u32 = (1 << Self::ACCESS_BITS) - 1;
-    const TYPE_MASK: u32 = (1 << Self::TYPE_BITS) - 1;
-
-    pub fn device_type(&self) -> DeviceType {
-        self.0
+        self
     }
 
-    pub fn required_access(&self) -> RequiredAccess {
-        self.1
+    /// Completes the request with the result of its handler.
+    pub fn finish(self) -> Result<Completion, RequestError> {
+        match self.result.unwrap_or(Err(Error::INVALID_DEVICE_REQUEST)) {
+            Ok(size) => Ok(Completion::Complete(size, self.request.into())),
+            Err(e) => Err(RequestError(e, self.request.into())),
+        }
     }
+}
+
+fn dispatch<In, Out, F>(request: &mut IoControlRequest, handler: F) -> Result<usize, Error>
+where
+    In: Pod,
+    Out: Pod,
+    F: FnOnce(&In) -> Result<Out, Error>,
+{
+    let mut user_ptr = request.user_ptr();
 
-    pub fn number(&self) -> u32 {
-        self.2
+    if user_ptr.read_size() < size_of::<In>() {
+        return Err(Error::INVALID_PARAMETER);
     }
 
-    pub fn transfer_method(&self) -> TransferMethod {
-        self.3
+    if user_ptr.write_size() < size_of::<Out>() {
+        return Err(Error::BUFFER_TOO_SMALL);
     }
-}
 
-impl From<u32> for ControlCode {
-    fn from(value: u32) -> Self {
-        let method = (value >> Self::METHOD_SHIFT) & Self::METHOD_MASK;
-        let num = (value >> Self::NUM_SHIFT) & Self::NUM_MASK;
-        let access = (value >> Self::ACCESS_SHIFT) & Self::ACCESS_MASK;
-        let ty = (value >> Self::TYPE_SHIFT) & Self::TYPE_MASK;
+    // For buffered requests the input and output share the same buffer, so the input is copied
+    // out before the output is written.
+    let input = user_ptr.read()?;
 
-        Self(
-            ty.into(),
-            RequiredAccess::from_bits(access).unwrap_or(RequiredAccess::READ_DATA),
-            num,
-            method.into(),
-        )
-    }
-}
+    let output = handler(&input)?;
 
-impl Into<u32> for ControlCode {
-    fn into(self) -> u32 {
-        let method = Into::<u32>::into(self.3) << Self::METHOD_SHIFT;
-        let num = self.2 << Self::NUM_SHIFT;
-        let access = self.1.bits() << Self::ACCESS_SHIFT;
-        let ty = Into::<u32>::into(self.0) << Self::TYPE_SHIFT;
+    user_ptr.write(&output)?;
 
-        ty | access | num | method
-    }
+    Ok(size_of::<Out>())
 }
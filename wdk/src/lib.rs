@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(alloc_error_handler)]
+#![feature(associated_type_defaults)]
 
 extern crate alloc;
 
@@ -8,12 +9,22 @@ pub mod dbg;
 pub mod device;
 pub mod driver;
 pub mod error;
+pub mod filter;
+pub mod guid;
+pub mod interface;
 pub mod ioctl;
+pub mod mdl;
+pub mod module;
+pub mod pnp;
+pub mod power;
 pub mod reg;
 pub mod request;
+pub mod sddl;
 pub mod string;
 pub mod symbolic_link;
 pub mod sync;
+pub mod target;
+pub mod user_memory;
 pub mod user_ptr;
 pub mod version;
 
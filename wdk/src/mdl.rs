@@ -0,0 +1,476 @@
+//! Memory descriptor lists, which describe the physical pages behind a virtual buffer. An MDL is
+//! needed to lock a buffer into memory, so it can be accessed at a raised IRQL, from another
+//! process context or by a device, and to map it into the system address space.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use wdk_sys::base::{
+    MdlMappingNoExecute, KAPC_STATE, MDL, MDL_MAPPED_TO_SYSTEM_VA, MDL_PAGES_LOCKED, MDL_PARTIAL,
+    MDL_SOURCE_IS_NONPAGED_POOL, PEPROCESS, _LOCK_OPERATION as LOCK_OPERATION,
+    _MEMORY_CACHING_TYPE as MEMORY_CACHING_TYPE, _MM_PAGE_PRIORITY as MM_PAGE_PRIORITY,
+    _MODE as MODE,
+};
+use wdk_sys::ntoskrnl::{
+    IoAllocateMdl, IoBuildPartialMdl, IoFreeMdl, KeStackAttachProcess, KeUnstackDetachProcess,
+    MmGetMdlByteCount, MmGetMdlByteOffset, MmGetMdlVirtualAddress, MmGetSystemAddressForMdlSafe,
+    MmMapLockedPagesSpecifyCacheGuarded, MmPrepareMdlForReuse, MmProbeAndLockPagesGuarded,
+    MmUnlockPages, MmUnmapLockedPages, ObDereferenceObject, ObReferenceObject, PsGetCurrentProcess,
+};
+
+use crate::error::{Error, IntoResult};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessMode {
+    KernelMode,
+    UserMode,
+}
+
+impl From<AccessMode> for MODE::Type {
+    fn from(mode: AccessMode) -> Self {
+        match mode {
+            AccessMode::KernelMode => MODE::KernelMode,
+            AccessMode::UserMode => MODE::UserMode,
+        }
+    }
+}
+
+/// The access a driver needs to pages it locks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LockOperation {
+    /// The driver reads from the pages, e.g. to write them to a device.
+    Read,
+    /// The driver writes to the pages, e.g. to read into them from a device.
+    Write,
+    Modify,
+}
+
+impl From<LockOperation> for LOCK_OPERATION::Type {
+    fn from(operation: LockOperation) -> Self {
+        match operation {
+            LockOperation::Read => LOCK_OPERATION::IoReadAccess,
+            LockOperation::Write => LOCK_OPERATION::IoWriteAccess,
+            LockOperation::Modify => LOCK_OPERATION::IoModifyAccess,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CacheType {
+    NonCached,
+    Cached,
+    WriteCombined,
+}
+
+impl From<CacheType> for MEMORY_CACHING_TYPE::Type {
+    fn from(cache_type: CacheType) -> Self {
+        match cache_type {
+            CacheType::NonCached => MEMORY_CACHING_TYPE::MmNonCached,
+            CacheType::Cached => MEMORY_CACHING_TYPE::MmCached,
+            CacheType::WriteCombined => MEMORY_CACHING_TYPE::MmWriteCombined,
+        }
+    }
+}
+
+/// How important it is that a mapping succeeds when system PTEs are running low.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PagePriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl From<PagePriority> for MM_PAGE_PRIORITY::Type {
+    fn from(priority: PagePriority) -> Self {
+        match priority {
+            PagePriority::Low => MM_PAGE_PRIORITY::LowPagePriority,
+            PagePriority::Normal => MM_PAGE_PRIORITY::NormalPagePriority,
+            PagePriority::High => MM_PAGE_PRIORITY::HighPagePriority,
+        }
+    }
+}
+
+/// A borrowed MDL, e.g. one of the MDLs that describe the buffer of a request.
+#[repr(transparent)]
+pub struct MdlRef(UnsafeCell<MDL>);
+
+impl MdlRef {
+    /// # Safety
+    ///
+    /// `mdl` has to point to a valid MDL that outlives `'a`, as do all MDLs chained to it.
+    pub unsafe fn from_raw<'a>(mdl: *mut MDL) -> &'a Self {
+        &*(mdl as *const Self)
+    }
+
+    pub fn as_raw(&self) -> *mut MDL {
+        self.0.get()
+    }
+
+    /// Returns the size of the buffer described by the MDL in bytes.
+    pub fn byte_count(&self) -> usize {
+        unsafe { MmGetMdlByteCount(self.as_raw()) as usize }
+    }
+
+    /// Returns the offset of the buffer into its first page.
+    pub fn byte_offset(&self) -> usize {
+        unsafe { MmGetMdlByteOffset(self.as_raw()) as usize }
+    }
+
+    /// Returns the virtual address of the buffer in the address space it was described in, which
+    /// may be a user-mode address of another process.
+    pub fn virtual_address(&self) -> *mut cty::c_void {
+        unsafe { MmGetMdlVirtualAddress(self.as_raw()) }
+    }
+
+    /// Returns whether the pages of the MDL are resident, i.e. they were locked, are from the
+    /// non-paged pool or are part of another MDL, and can be mapped.
+    pub fn is_locked(&self) -> bool {
+        let flags = unsafe { (*self.as_raw()).MdlFlags } as u32;
+
+        flags & (MDL_PAGES_LOCKED | MDL_SOURCE_IS_NONPAGED_POOL | MDL_PARTIAL) != 0
+    }
+
+    /// Maps the pages into the system address space, unless they are already mapped, and returns
+    /// the system address of the buffer. The mapping is released along with the MDL.
+    pub fn system_address(&self, priority: PagePriority) -> Result<NonNull<u8>, Error> {
+        if !self.is_locked() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let priority = MM_PAGE_PRIORITY::Type::from(priority) as u32 | MdlMappingNoExecute;
+        let ptr = unsafe { MmGetSystemAddressForMdlSafe(self.as_raw(), priority) };
+
+        NonNull::new(ptr as *mut u8).ok_or(Error::INSUFFICIENT_RESOURCES)
+    }
+
+    /// Builds an MDL for `length` bytes at `offset` into the buffer of this MDL, which has to be
+    /// locked. The partial MDL shares the pages of this MDL, and can therefore not outlive it.
+    pub fn partial(&self, offset: usize, length: usize) -> Result<PartialMdl<'_>, Error> {
+        if !self.is_locked() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        match offset.checked_add(length) {
+            Some(end) if length != 0 && end <= self.byte_count() => {}
+            _ => return Err(Error::INVALID_PARAMETER),
+        }
+
+        let address = unsafe { (self.virtual_address() as *mut u8).add(offset) } as _;
+        let mdl = unsafe { Mdl::new(address, length)? };
+
+        unsafe {
+            IoBuildPartialMdl(self.as_raw(), mdl.as_raw(), address, length as _);
+        }
+
+        Ok(PartialMdl {
+            mdl,
+            _source: PhantomData,
+        })
+    }
+
+    /// Returns the next MDL in the chain.
+    pub fn next(&self) -> Option<&MdlRef> {
+        let next = unsafe { (*self.as_raw()).Next };
+
+        if next.is_null() {
+            None
+        } else {
+            Some(unsafe { MdlRef::from_raw(next) })
+        }
+    }
+
+    /// Iterates over this MDL and all MDLs chained after it.
+    pub fn iter(&self) -> MdlIter<'_> {
+        MdlIter { next: Some(self) }
+    }
+}
+
+pub struct MdlIter<'a> {
+    next: Option<&'a MdlRef>,
+}
+
+impl<'a> Iterator for MdlIter<'a> {
+    type Item = &'a MdlRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mdl = self.next?;
+
+        self.next = mdl.next();
+
+        Some(mdl)
+    }
+}
+
+/// An MDL that is owned by the driver. On drop, the MDL unmaps and unlocks its pages before it is
+/// freed.
+pub struct Mdl {
+    raw: NonNull<MDL>,
+    locked: bool,
+    mapping: Option<NonNull<cty::c_void>>,
+    user_mapping: Option<(NonNull<cty::c_void>, PEPROCESS)>,
+}
+
+// An MDL is not tied to the thread that allocated it. A user-mode mapping is tied to its process,
+// but it borrows the MDL, so the MDL cannot be moved to another thread while it is mapped. If the
+// mapping was leaked instead, the MDL unmaps it from the process it was made in.
+unsafe impl Send for Mdl {}
+
+impl Mdl {
+    /// Allocates an MDL for the buffer of `length` bytes at `address`, whose pages still have to
+    /// be locked with [`Mdl::probe_and_lock`].
+    ///
+    /// # Safety
+    ///
+    /// If the pages are locked with [`AccessMode::KernelMode`], the buffer has to be valid kernel
+    /// memory, as it is not probed. A user-mode buffer has to be locked in the context of the
+    /// process it belongs to.
+    pub unsafe fn new(address: *mut cty::c_void, length: usize) -> Result<Self, Error> {
+        if length == 0 || length > u32::MAX as usize {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let raw = IoAllocateMdl(
+            address,
+            length as _,
+            false as _,
+            false as _,
+            core::ptr::null_mut(),
+        );
+
+        match NonNull::new(raw) {
+            Some(raw) => Ok(Self {
+                raw,
+                locked: false,
+                mapping: None,
+                user_mapping: None,
+            }),
+            None => Err(Error::INSUFFICIENT_RESOURCES),
+        }
+    }
+
+    /// Probes the pages of the buffer for the given access and locks them into memory. If the
+    /// pages cannot be accessed, or `mode` is [`AccessMode::UserMode`] and the buffer is not a
+    /// user-mode buffer, this fails with the exception code, e.g. `STATUS_ACCESS_VIOLATION`.
+    pub fn probe_and_lock(
+        &mut self,
+        mode: AccessMode,
+        operation: LockOperation,
+    ) -> Result<(), Error> {
+        if self.is_locked() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let raw = self.as_raw();
+
+        unsafe {
+            MmProbeAndLockPagesGuarded(
+                raw,
+                MODE::Type::from(mode) as _,
+                LOCK_OPERATION::Type::from(operation),
+            )
+        }
+        .into_result()?;
+
+        self.locked = true;
+
+        Ok(())
+    }
+
+    /// Maps the locked pages into the system address space, and returns the address of the buffer
+    /// in it. The pages are unmapped with [`Mdl::unmap`], or when the MDL is dropped.
+    ///
+    /// The pages can only be mapped into the system address space once, so this fails if they
+    /// already were, e.g. by [`MdlRef::system_address`].
+    pub fn map(
+        &mut self,
+        cache_type: CacheType,
+        priority: PagePriority,
+    ) -> Result<NonNull<u8>, Error> {
+        if !self.is_locked() || self.mapping.is_some() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let flags = unsafe { (*self.as_raw()).MdlFlags } as u32;
+
+        if flags & MDL_MAPPED_TO_SYSTEM_VA != 0 {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let ptr = self.map_locked_pages(AccessMode::KernelMode, cache_type, priority)?;
+
+        self.mapping = Some(ptr);
+
+        Ok(ptr.cast())
+    }
+
+    /// Maps the locked pages into the address space of the current process. The mapping is
+    /// released when the returned guard is dropped, which has to happen on the same thread, and
+    /// switches to the process the mapping was made in if the thread is attached to another one.
+    pub fn map_user(&mut self, cache_type: CacheType) -> Result<UserMapping<'_>, Error> {
+        if !self.is_locked() || self.user_mapping.is_some() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let ptr = self.map_locked_pages(AccessMode::UserMode, cache_type, PagePriority::Normal)?;
+        let process = unsafe { PsGetCurrentProcess() };
+
+        // The process is referenced, so it can still be attached to if the mapping is released
+        // from another process context.
+        unsafe { ObReferenceObject(process as _) };
+
+        self.user_mapping = Some((ptr, process));
+
+        Ok(UserMapping {
+            mdl: self,
+            ptr: ptr.cast(),
+            _not_send: PhantomData,
+        })
+    }
+
+    fn map_locked_pages(
+        &mut self,
+        mode: AccessMode,
+        cache_type: CacheType,
+        priority: PagePriority,
+    ) -> Result<NonNull<cty::c_void>, Error> {
+        let raw = self.as_raw();
+        let priority = MM_PAGE_PRIORITY::Type::from(priority) as u32 | MdlMappingNoExecute;
+        let mut ptr = core::ptr::null_mut();
+
+        // A user-mode mapping raises an exception rather than returning `NULL` when it fails.
+        unsafe {
+            MmMapLockedPagesSpecifyCacheGuarded(
+                raw,
+                MODE::Type::from(mode) as _,
+                MEMORY_CACHING_TYPE::Type::from(cache_type),
+                core::ptr::null_mut(),
+                false as _,
+                priority,
+                &mut ptr,
+            )
+        }
+        .into_result()?;
+
+        NonNull::new(ptr).ok_or(Error::INSUFFICIENT_RESOURCES)
+    }
+
+    /// Releases the mapping made by [`Mdl::map`], if any.
+    pub fn unmap(&mut self) {
+        if let Some(ptr) = self.mapping.take() {
+            unsafe {
+                MmUnmapLockedPages(ptr.as_ptr(), self.as_raw());
+            }
+        }
+    }
+
+    /// Releases the mapping made by [`Mdl::map_user`], if any, in the context of the process it was
+    /// made in.
+    fn unmap_user(&mut self) {
+        let (ptr, process) = match self.user_mapping.take() {
+            Some(mapping) => mapping,
+            None => return,
+        };
+
+        unsafe {
+            if PsGetCurrentProcess() == process {
+                MmUnmapLockedPages(ptr.as_ptr(), self.as_raw());
+            } else {
+                let mut state = core::mem::zeroed::<KAPC_STATE>();
+
+                KeStackAttachProcess(process as _, &mut state);
+                MmUnmapLockedPages(ptr.as_ptr(), self.as_raw());
+                KeUnstackDetachProcess(&mut state);
+            }
+
+            ObDereferenceObject(process as _);
+        }
+    }
+
+    /// Returns the buffer through its system address, see [`MdlRef::system_address`].
+    pub fn as_slice(&self) -> Result<&[u8], Error> {
+        let ptr = self.system_address(PagePriority::Normal)?;
+
+        Ok(unsafe { core::slice::from_raw_parts(ptr.as_ptr(), self.byte_count()) })
+    }
+
+    /// Returns the buffer through its system address, see [`MdlRef::system_address`].
+    pub fn as_mut_slice(&mut self) -> Result<&mut [u8], Error> {
+        let ptr = self.system_address(PagePriority::Normal)?;
+
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), self.byte_count()) })
+    }
+}
+
+impl core::ops::Deref for Mdl {
+    type Target = MdlRef;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { MdlRef::from_raw(self.raw.as_ptr()) }
+    }
+}
+
+impl Drop for Mdl {
+    fn drop(&mut self) {
+        self.unmap_user();
+        self.unmap();
+
+        let flags = unsafe { (*self.as_raw()).MdlFlags } as u32;
+
+        unsafe {
+            // A partial MDL does not own its pages, but it may have been mapped.
+            if flags & MDL_PARTIAL != 0 {
+                MmPrepareMdlForReuse(self.as_raw());
+            }
+
+            if self.locked {
+                MmUnlockPages(self.as_raw());
+            }
+
+            IoFreeMdl(self.as_raw());
+        }
+    }
+}
+
+/// A mapping of the pages of an [`Mdl`] into the address space of a process, as returned by
+/// [`Mdl::map_user`]. It is tied to the thread that made it, and releases the mapping when it is
+/// dropped.
+pub struct UserMapping<'a> {
+    mdl: &'a mut Mdl,
+    ptr: NonNull<u8>,
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl<'a> UserMapping<'a> {
+    /// Returns the user-mode address of the buffer, which is only valid in the process the mapping
+    /// was made in.
+    pub fn address(&self) -> NonNull<u8> {
+        self.ptr
+    }
+}
+
+impl<'a> Drop for UserMapping<'a> {
+    fn drop(&mut self) {
+        self.mdl.unmap_user();
+    }
+}
+
+/// An MDL for part of the buffer of another MDL, as returned by [`MdlRef::partial`].
+pub struct PartialMdl<'a> {
+    mdl: Mdl,
+    _source: PhantomData<&'a MdlRef>,
+}
+
+impl<'a> core::ops::Deref for PartialMdl<'a> {
+    type Target = Mdl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.mdl
+    }
+}
+
+impl<'a> core::ops::DerefMut for PartialMdl<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.mdl
+    }
+}
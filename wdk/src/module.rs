@@ -0,0 +1,94 @@
+//! This module provides the entry point of a driver. A driver implements [`KernelModule`] and
+//! declares it with [`kernel_module!`], which generates the `driver_entry` function that
+//! `wdk-build` passes to the linker as the entry point (`/ENTRY:driver_entry`).
+//!
+//! ```ignore
+//! struct Example;
+//!
+//! impl KernelModule for Example {
+//!     fn init(driver: &mut Driver, _registry_path: &UnicodeString) -> Result<Self, Error> {
+//!         let device = driver.device_builder().name("\\Device\\Example").build(MyDevice)?;
+//!         driver.register_device(device)?;
+//!
+//!         Ok(Example)
+//!     }
+//! }
+//!
+//! kernel_module!(Example);
+//! ```
+
+use wdk_sys::base::STATUS_SUCCESS;
+
+use crate::device::dispatch_device;
+use crate::driver::{init_resources, install_unload, release_resources, Driver};
+use crate::error::Error;
+use crate::string::UnicodeString;
+
+/// A driver. The module is created when the driver is loaded and dropped when it is unloaded,
+/// before the resources that were registered with the driver in [`KernelModule::init`].
+pub trait KernelModule: Send + Sized + 'static {
+    /// Initializes the driver. `registry_path` is the path of the driver's service key.
+    ///
+    /// If this fails, the resources that were registered with the driver are dropped again and
+    /// the driver is not loaded.
+    fn init(driver: &mut Driver, registry_path: &UnicodeString) -> Result<Self, Error>;
+}
+
+/// Declares the [`KernelModule`] of the driver by generating its `driver_entry` function.
+#[macro_export]
+macro_rules! kernel_module {
+    ($module:ty) => {
+        #[no_mangle]
+        pub unsafe extern "system" fn driver_entry(
+            driver: *mut $crate::module::DRIVER_OBJECT,
+            registry_path: *const $crate::module::UNICODE_STRING,
+        ) -> $crate::module::NTSTATUS {
+            $crate::module::driver_entry::<$module>(driver, registry_path)
+        }
+    };
+}
+
+#[doc(hidden)]
+pub use wdk_sys::base::{DRIVER_OBJECT, NTSTATUS, UNICODE_STRING};
+
+/// The entry point of the driver, which is called through [`kernel_module!`].
+#[doc(hidden)]
+pub unsafe fn driver_entry<T: KernelModule>(
+    driver: *mut DRIVER_OBJECT,
+    registry_path: *const UNICODE_STRING,
+) -> NTSTATUS {
+    for major in (*driver).MajorFunction.iter_mut() {
+        *major = Some(dispatch_device);
+    }
+
+    if let Err(e) = init_resources(driver) {
+        return e.to_ntstatus();
+    }
+
+    let mut drv = Driver::from_raw(driver);
+
+    match init::<T>(&mut drv, registry_path) {
+        Ok(()) => {
+            install_unload(driver);
+
+            STATUS_SUCCESS
+        }
+        Err(e) => {
+            // The I/O manager does not call DriverUnload if DriverEntry fails.
+            release_resources(driver);
+
+            e.to_ntstatus()
+        }
+    }
+}
+
+unsafe fn init<T: KernelModule>(
+    driver: &mut Driver,
+    registry_path: *const UNICODE_STRING,
+) -> Result<(), Error> {
+    let registry_path = UnicodeString::from_unicode_string(&*registry_path)?;
+    let module = T::init(driver, &registry_path)?;
+
+    // The module is registered last, such that it is dropped first when the driver is unloaded.
+    driver.register_resource(module)
+}
@@ -0,0 +1,487 @@
+//! This module provides support for Plug and Play (WDM) function drivers: a functional device
+//! object is created and attached to the physical device object in `AddDevice`, after which the
+//! `IRP_MJ_PNP` requests for it are tracked by a state machine and dispatched to [`PnpOperations`].
+
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+
+use wdk_sys::base::{
+    CM_FULL_RESOURCE_DESCRIPTOR, CM_PARTIAL_RESOURCE_DESCRIPTOR, CM_RESOURCE_LIST, DEVICE_OBJECT,
+    DRIVER_OBJECT, IRP, IRP_MJ_CLEANUP, IRP_MJ_CLOSE, IRP_MJ_PNP, IRP_MJ_POWER,
+    IRP_MJ_SYSTEM_CONTROL, IRP_MN_CANCEL_REMOVE_DEVICE, IRP_MN_CANCEL_STOP_DEVICE,
+    IRP_MN_QUERY_REMOVE_DEVICE, IRP_MN_QUERY_STOP_DEVICE, IRP_MN_REMOVE_DEVICE,
+    IRP_MN_START_DEVICE, IRP_MN_STOP_DEVICE, IRP_MN_SURPRISE_REMOVAL, NTSTATUS, PVOID,
+    STATUS_SUCCESS,
+};
+use wdk_sys::ntoskrnl::IoAttachDeviceToDeviceStack;
+
+use crate::device::{
+    dispatch_callback, release_callback, Device, DeviceDoFlags, DeviceFlags, DeviceOperations,
+    DeviceOperationsVtable, DeviceType, LowerDevice, Operations,
+};
+use crate::driver::Driver;
+use crate::error::Error;
+use crate::power::{dispatch_power, query_device_states, PowerOperations};
+use crate::request::IoRequest;
+
+/// The Plug and Play state of a functional device object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PnpState {
+    NotStarted,
+    Started,
+    StopPending,
+    Stopped,
+    RemovePending,
+    SurpriseRemovePending,
+    Deleted,
+}
+
+impl From<u32> for PnpState {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => PnpState::NotStarted,
+            1 => PnpState::Started,
+            2 => PnpState::StopPending,
+            3 => PnpState::Stopped,
+            4 => PnpState::RemovePending,
+            5 => PnpState::SurpriseRemovePending,
+            _ => PnpState::Deleted,
+        }
+    }
+}
+
+/// The physical device object that a functional device object is attached to.
+pub struct PhysicalDevice {
+    raw: *mut DEVICE_OBJECT,
+}
+
+impl PhysicalDevice {
+    pub unsafe fn from_raw(raw: *mut DEVICE_OBJECT) -> Self {
+        Self { raw }
+    }
+
+    pub unsafe fn as_raw(&self) -> *const DEVICE_OBJECT {
+        self.raw as *const _
+    }
+
+    pub unsafe fn as_raw_mut(&self) -> *mut DEVICE_OBJECT {
+        self.raw
+    }
+}
+
+/// A hardware resource list as passed along with `IRP_MN_START_DEVICE`.
+pub struct ResourceList<'a> {
+    raw: *const CM_RESOURCE_LIST,
+    _marker: PhantomData<&'a CM_RESOURCE_LIST>,
+}
+
+impl<'a> ResourceList<'a> {
+    pub unsafe fn from_raw(raw: *const CM_RESOURCE_LIST) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_null() || unsafe { (*self.raw).Count } == 0
+    }
+
+    /// Iterates over the partial resource descriptors of all the full resource descriptors in the
+    /// list.
+    pub fn iter(&self) -> ResourceIter<'a> {
+        if self.raw.is_null() {
+            return ResourceIter {
+                full: core::ptr::null(),
+                remaining: 0,
+                index: 0,
+                _marker: PhantomData,
+            };
+        }
+
+        ResourceIter {
+            full: unsafe { (*self.raw).List.as_ptr() },
+            remaining: unsafe { (*self.raw).Count },
+            index: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+pub struct ResourceIter<'a> {
+    full: *const CM_FULL_RESOURCE_DESCRIPTOR,
+    remaining: u32,
+    index: u32,
+    _marker: PhantomData<&'a CM_RESOURCE_LIST>,
+}
+
+impl<'a> Iterator for ResourceIter<'a> {
+    type Item = &'a CM_PARTIAL_RESOURCE_DESCRIPTOR;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            let list = unsafe { &(*self.full).PartialResourceList };
+            let descriptors = list.PartialDescriptors.as_ptr();
+
+            if self.index < list.Count {
+                let descriptor = unsafe { &*descriptors.add(self.index as usize) };
+                self.index += 1;
+
+                return Some(descriptor);
+            }
+
+            // Full resource descriptors are variable in size: the next one starts right after the
+            // last partial resource descriptor of the current one.
+            self.full = unsafe { descriptors.add(list.Count as usize) } as *const _;
+            self.remaining -= 1;
+            self.index = 0;
+        }
+
+        None
+    }
+}
+
+/// The operations of a Plug and Play function driver. `IRP_MJ_POWER` requests are dispatched to
+/// the [`PowerOperations`] of the device, while the I/O requests other than `IRP_MJ_PNP` are
+/// dispatched to the [`DeviceOperations`] of the device as usual.
+///
+/// I/O requests that arrive while the device is stopped, or about to be stopped, fail with
+/// `STATUS_DEVICE_NOT_READY`, and the ones that arrive while the device is being removed fail with
+/// `STATUS_DELETE_PENDING`. Closing and cleaning up handles is dispatched in any state.
+///
+/// Any Plug and Play request that is not handled is passed down to the lower device.
+pub trait PnpOperations: DeviceOperations + PowerOperations {
+    const DEVICE_TYPE: DeviceType = DeviceType::Unknown;
+    const DEVICE_FLAGS: DeviceFlags = DeviceFlags::SECURE_OPEN;
+    const DEVICE_DO_FLAGS: DeviceDoFlags = DeviceDoFlags::DO_BUFFERED_IO;
+
+    /// Called from `AddDevice` to create the device data for the functional device object that
+    /// will be attached to the given physical device object.
+    fn add_device(driver: &mut Driver, pdo: &PhysicalDevice) -> Result<Self, Error>;
+
+    /// Called once the lower devices have started the device, with the raw and translated
+    /// hardware resources that have been assigned to it.
+    fn start(
+        &self,
+        _device: &Device,
+        _raw: &ResourceList,
+        _translated: &ResourceList,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn query_stop(&self, _device: &Device) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when the device stops running, to release the hardware resources that were
+    /// passed to [`PnpOperations::start`]. This happens on `IRP_MN_STOP_DEVICE`, and on a surprise
+    /// removal or a removal of a device that was started.
+    fn stop(&self, _device: &Device) {}
+
+    fn query_remove(&self, _device: &Device) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn surprise_removal(&self, _device: &Device) {}
+
+    /// Called before the device is detached from the device stack and deleted. Any pending
+    /// requests of the device have to be completed here, e.g. by flushing its [`IrpQueue`], as
+    /// the device waits for them before it is deleted.
+    ///
+    /// [`IrpQueue`]: crate::request::IrpQueue
+    fn remove(&self, _device: &Device) {}
+}
+
+pub struct PnpOperationsVtable<T>(PhantomData<T>);
+
+impl<T: PnpOperations> PnpOperationsVtable<T> {
+    pub const VTABLE: Operations = Operations {
+        dispatch: Some(pnp_dispatch_callback::<T>),
+        release: Some(release_callback::<T>),
+        release_file_state: DeviceOperationsVtable::<T>::VTABLE.release_file_state,
+        unchecked_access: T::UNCHECKED_ACCESS,
+    };
+}
+
+impl Driver {
+    /// Installs `AddDevice` for the driver, such that a functional device object with `T` as its
+    /// device data is created for every device that the driver is loaded for.
+    pub fn set_add_device<T: PnpOperations>(&mut self) {
+        unsafe {
+            (*(*self.raw).DriverExtension).AddDevice = Some(add_device_callback::<T>);
+        }
+    }
+}
+
+extern "C" fn add_device_callback<T: PnpOperations>(
+    driver: *mut DRIVER_OBJECT,
+    pdo: *mut DEVICE_OBJECT,
+) -> NTSTATUS {
+    let mut driver = unsafe { Driver::from_raw(driver) };
+    let pdo = unsafe { PhysicalDevice::from_raw(pdo) };
+
+    match add_device::<T>(&mut driver, &pdo) {
+        Ok(()) => STATUS_SUCCESS,
+        Err(e) => e.to_ntstatus(),
+    }
+}
+
+fn add_device<T: PnpOperations>(driver: &mut Driver, pdo: &PhysicalDevice) -> Result<(), Error> {
+    let data = T::add_device(driver, pdo)?;
+
+    let device = driver
+        .device_builder()
+        .device_type(T::DEVICE_TYPE)
+        .characteristics(T::DEVICE_FLAGS)
+        .do_flags(T::DEVICE_DO_FLAGS | DeviceDoFlags::DO_POWER_PAGABLE)
+        .build_with_vtable(data, &PnpOperationsVtable::<T>::VTABLE)?;
+
+    let lower = unsafe { IoAttachDeviceToDeviceStack(device.as_raw_mut(), pdo.as_raw_mut()) };
+
+    if lower.is_null() {
+        // Dropping the device releases the data and deletes the device object.
+        return Err(Error::NO_SUCH_DEVICE);
+    }
+
+    unsafe {
+        // Nothing else can reach the device before it is attached.
+        (*device.extension_ptr()).physical_device = pdo.as_raw_mut();
+    }
+
+    let extension = device.extension();
+    extension.lower_device.store(lower, Ordering::Release);
+    extension
+        .pnp_state
+        .store(PnpState::NotStarted as u32, Ordering::Relaxed);
+    extension
+        .previous_pnp_state
+        .store(PnpState::NotStarted as u32, Ordering::Relaxed);
+
+    unsafe {
+        (*device.as_raw_mut()).Flags &= !wdk_sys::base::DO_DEVICE_INITIALIZING;
+    }
+
+    // The device is owned by the Plug and Play manager until IRP_MN_REMOVE_DEVICE.
+    device.into_raw();
+
+    Ok(())
+}
+
+extern "C" fn pnp_dispatch_callback<T: PnpOperations>(
+    device: *mut DEVICE_OBJECT,
+    irp: *mut IRP,
+    major: u8,
+) -> NTSTATUS {
+    let (lower, state) = {
+        let device = unsafe { Device::from_raw(device) };
+        let result = (device.lower_device(), device.pnp_state());
+        device.into_raw();
+        result
+    };
+
+    let request = unsafe { IoRequest::from_raw(irp) };
+
+    match major as _ {
+        IRP_MJ_PNP => dispatch_pnp::<T>(device, request),
+        IRP_MJ_POWER => {
+            let device = unsafe { Device::from_raw(device) };
+            let status = dispatch_power::<T>(&device, request);
+            device.into_raw();
+            status
+        }
+        IRP_MJ_SYSTEM_CONTROL => match lower {
+            Some(lower) => request.forward(&lower),
+            None => fail(request, Error::DELETE_PENDING),
+        },
+        // Handles have to be released whatever state the device is in.
+        IRP_MJ_CLEANUP | IRP_MJ_CLOSE => dispatch_callback::<T>(device, irp, major),
+        _ => match state {
+            PnpState::RemovePending | PnpState::SurpriseRemovePending | PnpState::Deleted => {
+                fail(request, Error::DELETE_PENDING)
+            }
+            PnpState::StopPending | PnpState::Stopped => fail(request, Error::DEVICE_NOT_READY),
+            _ => dispatch_callback::<T>(device, irp, major),
+        },
+    }
+}
+
+fn dispatch_pnp<T: PnpOperations>(device: *mut DEVICE_OBJECT, request: IoRequest) -> NTSTATUS {
+    let device = unsafe { Device::from_raw(device) };
+    let data: &T = device.data();
+
+    let lower = match device.lower_device() {
+        Some(lower) => lower,
+        None => {
+            // The device is not attached to a device stack, so there is nothing to pass the
+            // request on to. The handler of IRP_MN_REMOVE_DEVICE releases the remove lock itself.
+            if request.minor() as u32 == IRP_MN_REMOVE_DEVICE {
+                device.release_remove_lock(request.irp_mut() as *mut _ as PVOID);
+            }
+
+            device.into_raw();
+
+            return fail(request, Error::DELETE_PENDING);
+        }
+    };
+
+    let status = match request.minor() as _ {
+        IRP_MN_START_DEVICE => {
+            // The lower devices have to start the device before we can.
+            let status = request.forward_and_wait(&lower);
+
+            let status = if status < 0 {
+                status
+            } else {
+                let parameters = unsafe { request.stack_location().Parameters.StartDevice };
+                let raw = unsafe { ResourceList::from_raw(parameters.AllocatedResources) };
+                let translated =
+                    unsafe { ResourceList::from_raw(parameters.AllocatedResourcesTranslated) };
+
+                let result = query_device_states(&device, &lower)
+                    .and_then(|()| data.start(&device, &raw, &translated));
+
+                match result {
+                    Ok(()) => {
+                        set_state(&device, PnpState::Started);
+                        device.register_idle_detection(T::IDLE_DETECTION);
+                        STATUS_SUCCESS
+                    }
+                    Err(e) => e.to_ntstatus(),
+                }
+            };
+
+            request.complete_with_status(status);
+            status
+        }
+        IRP_MN_QUERY_STOP_DEVICE => match data.query_stop(&device) {
+            Ok(()) => {
+                set_state(&device, PnpState::StopPending);
+                pass_down(request, &lower)
+            }
+            Err(e) => {
+                request.complete(Err(e));
+                e.to_ntstatus()
+            }
+        },
+        IRP_MN_CANCEL_STOP_DEVICE => {
+            let status = request.forward_and_wait(&lower);
+
+            if status >= 0 && device.pnp_state() == PnpState::StopPending {
+                restore_state(&device);
+            }
+
+            request.complete_with_status(status);
+            status
+        }
+        IRP_MN_STOP_DEVICE => {
+            data.stop(&device);
+            set_state(&device, PnpState::Stopped);
+            pass_down(request, &lower)
+        }
+        IRP_MN_QUERY_REMOVE_DEVICE => match data.query_remove(&device) {
+            Ok(()) => {
+                set_state(&device, PnpState::RemovePending);
+                pass_down(request, &lower)
+            }
+            Err(e) => {
+                request.complete(Err(e));
+                e.to_ntstatus()
+            }
+        },
+        IRP_MN_CANCEL_REMOVE_DEVICE => {
+            let status = request.forward_and_wait(&lower);
+
+            if status >= 0 && device.pnp_state() == PnpState::RemovePending {
+                restore_state(&device);
+            }
+
+            request.complete_with_status(status);
+            status
+        }
+        IRP_MN_SURPRISE_REMOVAL => {
+            let previous = device.pnp_state();
+            set_state(&device, PnpState::SurpriseRemovePending);
+
+            data.surprise_removal(&device);
+
+            // The hardware is gone, so its resources are released right away rather than on
+            // IRP_MN_REMOVE_DEVICE.
+            if was_running(previous) {
+                data.stop(&device);
+            }
+
+            pass_down(request, &lower)
+        }
+        IRP_MN_REMOVE_DEVICE => {
+            let previous = device.pnp_state();
+            set_state(&device, PnpState::Deleted);
+
+            // A device that never started, or that was stopped or surprise removed before, is not
+            // running anymore.
+            if was_running(previous) {
+                data.stop(&device);
+            }
+
+            device.register_idle_detection(None);
+            data.remove(&device);
+
+            // Wait for the requests that are still being dispatched or pending, which `remove`
+            // has completed. The remove lock was acquired for this request when it was
+            // dispatched.
+            device.release_remove_lock_and_wait(request.irp_mut() as *mut _ as PVOID);
+
+            let status = pass_down(request, &lower);
+
+            // Dropping the device releases the data, detaches the device from the lower device
+            // and deletes the device object.
+            drop(device);
+
+            return status;
+        }
+        _ => request.forward(&lower),
+    };
+
+    device.into_raw();
+
+    status
+}
+
+/// Returns whether the hardware resources of a device in the given state still have to be
+/// released through [`PnpOperations::stop`].
+fn was_running(state: PnpState) -> bool {
+    matches!(
+        state,
+        PnpState::Started | PnpState::StopPending | PnpState::RemovePending
+    )
+}
+
+fn fail(request: IoRequest, error: Error) -> NTSTATUS {
+    request.complete(Err(error));
+    error.to_ntstatus()
+}
+
+/// Passes a request that we have handled successfully on to the lower device.
+fn pass_down(request: IoRequest, lower: &LowerDevice) -> NTSTATUS {
+    request.irp_mut().IoStatus.__bindgen_anon_1.Status = STATUS_SUCCESS;
+    request.forward(lower)
+}
+
+// The Plug and Play manager serializes IRP_MJ_PNP requests, so only the readers of the state run
+// concurrently with these.
+fn set_state(device: &Device, state: PnpState) {
+    let extension = device.extension();
+
+    let previous = extension.pnp_state.swap(state as u32, Ordering::Relaxed);
+    extension
+        .previous_pnp_state
+        .store(previous, Ordering::Relaxed);
+}
+
+fn restore_state(device: &Device) {
+    let extension = device.extension();
+
+    let previous = extension.previous_pnp_state.load(Ordering::Relaxed);
+    extension.pnp_state.store(previous, Ordering::Relaxed);
+}
@@ -0,0 +1,538 @@
+//! This module provides the `IRP_MJ_POWER` handling for Plug and Play function drivers.
+//!
+//! The function driver acts as the power policy owner of its device: when a system power state
+//! is set or queried, the lower drivers are given the system power request first, after which a
+//! device power request for the matching device power state is sent through [`PoRequestPowerIrp`].
+//! The matching device power state is the one that the device capabilities report for the system
+//! power state when the device is started. The system power request is completed once the device
+//! power request has finished. Device power requests
+//! are dispatched to [`PowerOperations`] in the right order: after the lower drivers when powering
+//! up and before the lower drivers when powering down. Any other power request is passed down.
+
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::Ordering;
+
+use wdk_sys::base::{
+    DEVICE_CAPABILITIES, DEVICE_OBJECT, IO_NO_INCREMENT, IO_STATUS_BLOCK, IRP, IRP_MJ_PNP,
+    IRP_MN_QUERY_CAPABILITIES, IRP_MN_QUERY_POWER, IRP_MN_SET_POWER, KEVENT, NTSTATUS, POWER_STATE,
+    PVOID, STATUS_MORE_PROCESSING_REQUIRED, STATUS_NOT_SUPPORTED, STATUS_PENDING, STATUS_SUCCESS,
+    _DEVICE_POWER_STATE as DEVICE_POWER_STATE, _EVENT_TYPE as EVENT_TYPE,
+    _KWAIT_REASON as KWAIT_REASON, _MODE as MODE, _POWER_STATE_TYPE as POWER_STATE_TYPE,
+    _SYSTEM_POWER_STATE as SYSTEM_POWER_STATE,
+};
+use wdk_sys::ntoskrnl::{
+    IoBuildSynchronousFsdRequest, IoCallDriver, IoCompleteRequest,
+    IoCopyCurrentIrpStackLocationToNext, IoGetNextIrpStackLocation, IoMarkIrpPending,
+    IoSetCompletionRoutine, IoSkipCurrentIrpStackLocation, KeInitializeEvent,
+    KeWaitForSingleObject, PoCallDriver, PoRegisterDeviceForIdleDetection, PoRequestPowerIrp,
+    PoSetPowerState, PoStartNextPowerIrp,
+};
+
+use crate::device::{Device, LowerDevice, RemoveLockGuard};
+use crate::error::{Error, IntoResult};
+use crate::pnp::PnpOperations;
+use crate::request::IoRequest;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SystemPowerState {
+    Unspecified,
+    Working,
+    Sleeping1,
+    Sleeping2,
+    Sleeping3,
+    Hibernate,
+    Shutdown,
+}
+
+impl Into<i32> for SystemPowerState {
+    fn into(self) -> i32 {
+        match self {
+            SystemPowerState::Unspecified => SYSTEM_POWER_STATE::PowerSystemUnspecified,
+            SystemPowerState::Working => SYSTEM_POWER_STATE::PowerSystemWorking,
+            SystemPowerState::Sleeping1 => SYSTEM_POWER_STATE::PowerSystemSleeping1,
+            SystemPowerState::Sleeping2 => SYSTEM_POWER_STATE::PowerSystemSleeping2,
+            SystemPowerState::Sleeping3 => SYSTEM_POWER_STATE::PowerSystemSleeping3,
+            SystemPowerState::Hibernate => SYSTEM_POWER_STATE::PowerSystemHibernate,
+            SystemPowerState::Shutdown => SYSTEM_POWER_STATE::PowerSystemShutdown,
+        }
+    }
+}
+
+impl From<i32> for SystemPowerState {
+    fn from(value: i32) -> Self {
+        match value {
+            SYSTEM_POWER_STATE::PowerSystemWorking => SystemPowerState::Working,
+            SYSTEM_POWER_STATE::PowerSystemSleeping1 => SystemPowerState::Sleeping1,
+            SYSTEM_POWER_STATE::PowerSystemSleeping2 => SystemPowerState::Sleeping2,
+            SYSTEM_POWER_STATE::PowerSystemSleeping3 => SystemPowerState::Sleeping3,
+            SYSTEM_POWER_STATE::PowerSystemHibernate => SystemPowerState::Hibernate,
+            SYSTEM_POWER_STATE::PowerSystemShutdown => SystemPowerState::Shutdown,
+            _ => SystemPowerState::Unspecified,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DevicePowerState {
+    Unspecified,
+    D0,
+    D1,
+    D2,
+    D3,
+}
+
+impl Into<i32> for DevicePowerState {
+    fn into(self) -> i32 {
+        match self {
+            DevicePowerState::Unspecified => DEVICE_POWER_STATE::PowerDeviceUnspecified,
+            DevicePowerState::D0 => DEVICE_POWER_STATE::PowerDeviceD0,
+            DevicePowerState::D1 => DEVICE_POWER_STATE::PowerDeviceD1,
+            DevicePowerState::D2 => DEVICE_POWER_STATE::PowerDeviceD2,
+            DevicePowerState::D3 => DEVICE_POWER_STATE::PowerDeviceD3,
+        }
+    }
+}
+
+impl From<i32> for DevicePowerState {
+    fn from(value: i32) -> Self {
+        match value {
+            DEVICE_POWER_STATE::PowerDeviceD0 => DevicePowerState::D0,
+            DEVICE_POWER_STATE::PowerDeviceD1 => DevicePowerState::D1,
+            DEVICE_POWER_STATE::PowerDeviceD2 => DevicePowerState::D2,
+            DEVICE_POWER_STATE::PowerDeviceD3 => DevicePowerState::D3,
+            _ => DevicePowerState::Unspecified,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    System(SystemPowerState),
+    Device(DevicePowerState),
+}
+
+/// Opts a device in to idle detection through [`PoRegisterDeviceForIdleDetection`]. The power
+/// manager requests `state` for the device once it has not been marked busy through
+/// [`Device::set_busy`] for the given number of seconds.
+#[derive(Copy, Clone, Debug)]
+pub struct IdleDetection {
+    pub conservation_timeout: u32,
+    pub performance_timeout: u32,
+    pub state: DevicePowerState,
+}
+
+fn power_state(request: &IoRequest) -> PowerState {
+    let parameters = unsafe { request.stack_location().Parameters.Power };
+
+    if parameters.Type == POWER_STATE_TYPE::SystemPowerState {
+        PowerState::System(unsafe { parameters.State.SystemState }.into())
+    } else {
+        PowerState::Device(unsafe { parameters.State.DeviceState }.into())
+    }
+}
+
+/// An `IRP_MN_SET_POWER` request.
+pub struct SetPowerRequest<'a> {
+    inner: &'a IoRequest,
+}
+
+impl<'a> SetPowerRequest<'a> {
+    pub fn state(&self) -> PowerState {
+        power_state(self.inner)
+    }
+
+    /// The system power action that caused the request, e.g. `PowerActionHibernate`.
+    pub fn action(&self) -> i32 {
+        unsafe { self.inner.stack_location().Parameters.Power.ShutdownType }
+    }
+}
+
+/// An `IRP_MN_QUERY_POWER` request.
+pub struct QueryPowerRequest<'a> {
+    inner: &'a IoRequest,
+}
+
+impl<'a> QueryPowerRequest<'a> {
+    pub fn state(&self) -> PowerState {
+        power_state(self.inner)
+    }
+
+    /// The system power action that caused the request, e.g. `PowerActionHibernate`.
+    pub fn action(&self) -> i32 {
+        unsafe { self.inner.stack_location().Parameters.Power.ShutdownType }
+    }
+}
+
+/// The power operations of a Plug and Play function driver. The default implementation accepts
+/// every power state.
+pub trait PowerOperations {
+    const IDLE_DETECTION: Option<IdleDetection> = None;
+
+    /// Called for every device power state change: after the lower drivers have powered up the
+    /// device, and before the lower drivers power down the device.
+    fn set_power(&self, _device: &Device, _request: &SetPowerRequest) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when the system or the power manager queries whether the device can enter a power
+    /// state. Returning an error vetoes the power state change.
+    fn query_power(&self, _device: &Device, _request: &QueryPowerRequest) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Marks the device busy, which resets the idle timer of a device that opted in to idle
+    /// detection through [`PowerOperations::IDLE_DETECTION`].
+    pub fn set_busy(&self) {
+        let counter = self.extension().idle_counter.load(Ordering::Acquire);
+
+        if !counter.is_null() {
+            unsafe { core::ptr::write_volatile(counter, 0) };
+        }
+    }
+
+    pub fn device_power_state(&self) -> DevicePowerState {
+        DevicePowerState::from(self.extension().device_power_state.load(Ordering::Relaxed))
+    }
+
+    /// Requests the power manager to send a device power request to put the device in the given
+    /// power state.
+    pub fn request_power(&self, state: DevicePowerState) -> Result<(), Error> {
+        let power_state = POWER_STATE {
+            DeviceState: state.into(),
+        };
+
+        unsafe {
+            PoRequestPowerIrp(
+                self.as_raw_mut(),
+                IRP_MN_SET_POWER as _,
+                power_state,
+                None,
+                null_mut(),
+                null_mut(),
+            )
+        }
+        .into_result()
+    }
+
+    /// Returns the device power state that the device is put in for the given system power state.
+    /// Unless the device capabilities report otherwise, the device is powered on in the working
+    /// state and powered off in any other state.
+    pub fn device_state_for(&self, state: SystemPowerState) -> DevicePowerState {
+        let index: i32 = state.into();
+
+        let reported = self
+            .extension()
+            .device_states
+            .get(index as usize)
+            .map(|device_state| DevicePowerState::from(device_state.load(Ordering::Relaxed)));
+
+        match reported {
+            Some(device_state) if device_state != DevicePowerState::Unspecified => device_state,
+            _ if state == SystemPowerState::Working => DevicePowerState::D0,
+            _ => DevicePowerState::D3,
+        }
+    }
+
+    pub(crate) fn register_idle_detection(&self, idle_detection: Option<IdleDetection>) {
+        let current = self.extension().idle_counter.load(Ordering::Acquire);
+
+        let counter = match idle_detection {
+            Some(idle) => unsafe {
+                PoRegisterDeviceForIdleDetection(
+                    self.as_raw_mut(),
+                    idle.conservation_timeout,
+                    idle.performance_timeout,
+                    idle.state.into(),
+                )
+            },
+            None if !current.is_null() => unsafe {
+                // Zero timeouts cancel the idle detection for the device.
+                PoRegisterDeviceForIdleDetection(
+                    self.as_raw_mut(),
+                    0,
+                    0,
+                    DevicePowerState::D3.into(),
+                )
+            },
+            None => null_mut(),
+        };
+
+        self.extension()
+            .idle_counter
+            .store(counter, Ordering::Release);
+    }
+}
+
+/// Queries the capabilities of the device from the lower drivers, and records the device power
+/// state that the device can be in for every system power state. This is called at
+/// `PASSIVE_LEVEL` once the lower drivers have started the device.
+pub(crate) fn query_device_states(device: &Device, lower: &LowerDevice) -> Result<(), Error> {
+    let mut capabilities: DEVICE_CAPABILITIES = unsafe { core::mem::zeroed() };
+    capabilities.Size = size_of::<DEVICE_CAPABILITIES>() as _;
+    capabilities.Version = 1;
+    capabilities.Address = u32::MAX;
+    capabilities.UINumber = u32::MAX;
+
+    let mut event: KEVENT = unsafe { core::mem::zeroed() };
+    let mut io_status: IO_STATUS_BLOCK = unsafe { core::mem::zeroed() };
+
+    let status = unsafe {
+        KeInitializeEvent(&mut event, EVENT_TYPE::NotificationEvent, 0);
+
+        let irp = IoBuildSynchronousFsdRequest(
+            IRP_MJ_PNP,
+            lower.as_raw_mut(),
+            null_mut(),
+            0,
+            null_mut(),
+            &mut event,
+            &mut io_status,
+        );
+
+        if irp.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        // Plug and Play requests start out as not supported, as the bus driver expects.
+        (*irp).IoStatus.__bindgen_anon_1.Status = STATUS_NOT_SUPPORTED;
+
+        let stack_location = IoGetNextIrpStackLocation(irp);
+        (*stack_location).MinorFunction = IRP_MN_QUERY_CAPABILITIES as _;
+        (*stack_location).Parameters.DeviceCapabilities.Capabilities = &mut capabilities;
+
+        let status = IoCallDriver(lower.as_raw_mut(), irp);
+
+        if status == STATUS_PENDING {
+            KeWaitForSingleObject(
+                &mut event as *mut KEVENT as _,
+                KWAIT_REASON::Executive,
+                MODE::KernelMode as _,
+                0,
+                null_mut(),
+            );
+
+            io_status.__bindgen_anon_1.Status
+        } else {
+            status
+        }
+    };
+
+    status.into_result()?;
+
+    let device_states = &device.extension().device_states;
+
+    for (device_state, reported) in device_states.iter().zip(capabilities.DeviceState) {
+        device_state.store(reported, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn dispatch_power<T: PnpOperations>(device: &Device, request: IoRequest) -> NTSTATUS {
+    let data: &T = device.data();
+
+    let lower = match device.lower_device() {
+        Some(lower) => lower,
+        None => {
+            unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+            request.complete(Err(Error::DELETE_PENDING));
+            return Error::DELETE_PENDING.to_ntstatus();
+        }
+    };
+
+    match (request.minor() as _, power_state(&request)) {
+        (IRP_MN_SET_POWER, PowerState::System(_)) => forward_system_power(device, request, &lower),
+        (IRP_MN_SET_POWER, PowerState::Device(state)) => {
+            let powering_up = state < device.device_power_state();
+
+            if powering_up {
+                // The lower drivers have to power up the device before we can.
+                let status = request.forward_and_wait(&lower);
+
+                if status < 0 {
+                    unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+                    request.complete_with_status(status);
+                    return status;
+                }
+            }
+
+            let result = data.set_power(device, &SetPowerRequest { inner: &request });
+
+            if result.is_ok() {
+                set_device_power_state(device, state);
+            }
+
+            unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+
+            match result {
+                Ok(()) if powering_up => {
+                    request.complete_with_status(STATUS_SUCCESS);
+                    STATUS_SUCCESS
+                }
+                Ok(()) => {
+                    request.irp_mut().IoStatus.__bindgen_anon_1.Status = STATUS_SUCCESS;
+                    pass_down(request, &lower)
+                }
+                Err(e) => {
+                    request.complete(Err(e));
+                    e.to_ntstatus()
+                }
+            }
+        }
+        (IRP_MN_QUERY_POWER, state) => {
+            let result = data.query_power(device, &QueryPowerRequest { inner: &request });
+
+            match result {
+                // The device is queried for the matching device power state in turn.
+                Ok(()) if matches!(state, PowerState::System(_)) => {
+                    forward_system_power(device, request, &lower)
+                }
+                Ok(()) => {
+                    request.irp_mut().IoStatus.__bindgen_anon_1.Status = STATUS_SUCCESS;
+                    unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+                    pass_down(request, &lower)
+                }
+                Err(e) => {
+                    unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+                    request.complete(Err(e));
+                    e.to_ntstatus()
+                }
+            }
+        }
+        _ => {
+            unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+            pass_down(request, &lower)
+        }
+    }
+}
+
+fn set_device_power_state(device: &Device, state: DevicePowerState) {
+    let power_state = POWER_STATE {
+        DeviceState: state.into(),
+    };
+
+    unsafe {
+        PoSetPowerState(
+            device.as_raw_mut(),
+            POWER_STATE_TYPE::DevicePowerState,
+            power_state,
+        );
+    }
+
+    device
+        .extension()
+        .device_power_state
+        .store(state.into(), Ordering::Relaxed);
+}
+
+pub(crate) fn pass_down(request: IoRequest, lower: &LowerDevice) -> NTSTATUS {
+    let irp = request.irp_mut();
+
+    unsafe {
+        IoSkipCurrentIrpStackLocation(irp);
+        PoCallDriver(lower.as_raw_mut(), irp)
+    }
+}
+
+/// Passes a system power request on to the lower drivers. Once they have completed it, the
+/// matching device power request, with the same minor function, is sent from
+/// [`system_power_completion`].
+fn forward_system_power(device: &Device, request: IoRequest, lower: &LowerDevice) -> NTSTATUS {
+    // The device is kept from being deleted until the completion routines have run, which
+    // release the reference again.
+    match RemoveLockGuard::acquire(&request) {
+        Ok(lock) => core::mem::forget(lock),
+        Err(_) => {
+            unsafe { PoStartNextPowerIrp(request.irp_mut()) };
+            return pass_down(request, lower);
+        }
+    }
+
+    let irp = request.irp_mut();
+
+    unsafe {
+        IoMarkIrpPending(irp);
+        IoCopyCurrentIrpStackLocationToNext(irp);
+        IoSetCompletionRoutine(
+            irp,
+            Some(system_power_completion),
+            device.as_raw_mut() as PVOID,
+            1,
+            1,
+            1,
+        );
+
+        PoCallDriver(lower.as_raw_mut(), irp);
+    }
+
+    STATUS_PENDING
+}
+
+/// This may run at `DISPATCH_LEVEL`, so it only touches the non-paged device extension.
+unsafe extern "C" fn system_power_completion(
+    _device: *mut DEVICE_OBJECT,
+    irp: *mut IRP,
+    context: PVOID,
+) -> NTSTATUS {
+    let device = context as *mut DEVICE_OBJECT;
+    let request = IoRequest::from_raw(irp);
+
+    if request.status() < 0 {
+        PoStartNextPowerIrp(irp);
+        drop(RemoveLockGuard::adopt(&request));
+        return STATUS_SUCCESS;
+    }
+
+    let state = match power_state(&request) {
+        PowerState::System(state) => {
+            let device = Device::from_raw(device);
+            let state = device.device_state_for(state);
+            device.into_raw();
+            state
+        }
+        PowerState::Device(state) => state,
+    };
+
+    let power_state = POWER_STATE {
+        DeviceState: state.into(),
+    };
+
+    let status = PoRequestPowerIrp(
+        device,
+        request.minor(),
+        power_state,
+        Some(device_power_completion),
+        irp as PVOID,
+        null_mut(),
+    );
+
+    if status < 0 {
+        (*irp).IoStatus.__bindgen_anon_1.Status = status;
+        PoStartNextPowerIrp(irp);
+        drop(RemoveLockGuard::adopt(&request));
+        return STATUS_SUCCESS;
+    }
+
+    // The system power request is completed once the device power request has finished.
+    STATUS_MORE_PROCESSING_REQUIRED
+}
+
+unsafe extern "C" fn device_power_completion(
+    _device: *mut DEVICE_OBJECT,
+    _minor: u8,
+    _state: POWER_STATE,
+    context: PVOID,
+    io_status: *mut IO_STATUS_BLOCK,
+) {
+    let irp = context as *mut IRP;
+    let lock = RemoveLockGuard::adopt(&IoRequest::from_raw(irp));
+
+    (*irp).IoStatus.__bindgen_anon_1.Status = (*io_status).__bindgen_anon_1.Status;
+
+    PoStartNextPowerIrp(irp);
+    IoCompleteRequest(irp, IO_NO_INCREMENT as _);
+    drop(lock);
+}
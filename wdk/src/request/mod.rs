@@ -0,0 +1,1278 @@
+use bitflags::bitflags;
+use core::ops::{Deref, DerefMut};
+
+use wdk_sys::base::{
+    DEVICE_OBJECT, KEVENT, NTSTATUS, STATUS_MORE_PROCESSING_REQUIRED, STATUS_PENDING,
+    _EVENT_TYPE as EVENT_TYPE, _KWAIT_REASON as KWAIT_REASON, _MODE as MODE,
+    _POOL_TYPE as POOL_TYPE,
+};
+use wdk_sys::base::{
+    FILE_INFORMATION_CLASS, FILE_OBJECT, FILE_USE_FILE_POINTER_POSITION, FO_SYNCHRONOUS_IO,
+    FS_INFORMATION_CLASS, IO_CD_ROM_INCREMENT, IO_DISK_INCREMENT, IO_KEYBOARD_INCREMENT,
+    IO_MAILSLOT_INCREMENT, IO_MOUSE_INCREMENT, IO_NAMED_PIPE_INCREMENT, IO_NETWORK_INCREMENT,
+    IO_NO_INCREMENT, IO_PARALLEL_INCREMENT, IO_SERIAL_INCREMENT, IO_SOUND_INCREMENT,
+    IO_STACK_LOCATION, IO_VIDEO_INCREMENT, IRP, IRP_MN_LOCK, IRP_MN_NOTIFY_CHANGE_DIRECTORY,
+    IRP_MN_QUERY_DIRECTORY, IRP_MN_UNLOCK_ALL, IRP_MN_UNLOCK_ALL_BY_KEY, IRP_MN_UNLOCK_SINGLE,
+    PVOID, STATUS_SUCCESS, UNICODE_STRING, _MM_PAGE_PRIORITY as MM_PAGE_PRIORITY,
+};
+use wdk_sys::ntoskrnl::{
+    IoCallDriver, IoCompleteRequest, IoCopyCurrentIrpStackLocationToNext,
+    IoGetCurrentIrpStackLocation, IoMarkIrpPending, IoSetCompletionRoutine,
+    IoSkipCurrentIrpStackLocation, KeInitializeEvent, KeSetEvent, KeWaitForSingleObject,
+    MmGetMdlByteCount, MmGetSystemAddressForMdlSafe,
+};
+
+use crate::allocator::Pool;
+use crate::device::{release_failed_create, LowerDevice, RemoveLockGuard, RequestError};
+use crate::error::Error;
+use crate::ioctl::{ControlCode, RequiredAccess, TransferMethod};
+use crate::mdl::MdlRef;
+use crate::user_ptr::{Buffer, UserPtr};
+
+mod queue;
+
+pub use queue::{IrpQueue, QueuedRequest};
+
+const COMPLETION_TAG: u32 = u32::from_ne_bytes(*b"rcmp");
+
+bitflags! {
+    pub struct IrpFlags: u32 {
+        const NOCACHE = wdk_sys::base::IRP_NOCACHE;
+        const PAGING_IO = wdk_sys::base::IRP_PAGING_IO;
+        const MOUNT_COMPLETION =  wdk_sys::base::IRP_MOUNT_COMPLETION;
+        const SYNCHRONOUS_API = wdk_sys::base::IRP_SYNCHRONOUS_API;
+        const ASSOCIATED_IRP = wdk_sys::base::IRP_ASSOCIATED_IRP;
+        const BUFFERED_IO = wdk_sys::base::IRP_BUFFERED_IO;
+        const DEALLOCATE_BUFFER = wdk_sys::base::IRP_DEALLOCATE_BUFFER;
+        const INPUT_OPERATION = wdk_sys::base::IRP_INPUT_OPERATION;
+        const SYNCHRONOUS_PAGING_IO = wdk_sys::base::IRP_SYNCHRONOUS_PAGING_IO;
+        const CREATE_OPERATION = wdk_sys::base::IRP_CREATE_OPERATION;
+        const READ_OPERATION = wdk_sys::base::IRP_READ_OPERATION;
+        const WRITE_OPERATION = wdk_sys::base::IRP_WRITE_OPERATION;
+        const CLOSE_OPERATION = wdk_sys::base::IRP_CLOSE_OPERATION;
+        const DEFER_IO_COMPLETION = wdk_sys::base::IRP_DEFER_IO_COMPLETION;
+        const OB_QUERY_NAME = wdk_sys::base::IRP_OB_QUERY_NAME;
+        const HOLD_DEVICE_QUEUE = wdk_sys::base::IRP_HOLD_DEVICE_QUEUE;
+        const UM_DRIVER_INITIATED_IO = wdk_sys::base::IRP_UM_DRIVER_INITIATED_IO;
+    }
+}
+
+pub struct IoRequest {
+    irp: *mut IRP,
+}
+
+impl IoRequest {
+    pub unsafe fn from_raw(irp: *mut IRP) -> Self {
+        Self { irp }
+    }
+
+    pub fn irp(&self) -> &IRP {
+        unsafe { &*self.irp }
+    }
+
+    pub fn irp_mut(&self) -> &mut IRP {
+        unsafe { &mut *self.irp }
+    }
+
+    pub fn flags(&self) -> IrpFlags {
+        IrpFlags::from_bits(self.irp().Flags).unwrap_or(IrpFlags::empty())
+    }
+
+    pub fn stack_location(&self) -> &IO_STACK_LOCATION {
+        unsafe { &*IoGetCurrentIrpStackLocation(self.irp_mut()) }
+    }
+
+    /// Returns the file object that the request was issued on, which may be null for requests
+    /// that were built by other drivers.
+    pub fn file_object(&self) -> *mut FILE_OBJECT {
+        self.stack_location().FileObject
+    }
+
+    /// Returns the MDL chain that describes the buffer of a direct I/O request, if any. Its pages
+    /// are locked until the request is completed.
+    pub fn mdl(&self) -> Option<&MdlRef> {
+        let mdl = self.irp().MdlAddress;
+
+        if mdl.is_null() {
+            None
+        } else {
+            Some(unsafe { MdlRef::from_raw(mdl) })
+        }
+    }
+
+    pub fn major(&self) -> u8 {
+        self.stack_location().MajorFunction
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.stack_location().MinorFunction
+    }
+
+    pub fn status(&self) -> NTSTATUS {
+        unsafe { self.irp().IoStatus.__bindgen_anon_1.Status }
+    }
+
+    /// Completes the request with the given status, leaving `Information` untouched.
+    pub(crate) fn complete_with_status(self, status: NTSTATUS) {
+        if status < 0 {
+            release_failed_create(&self);
+        }
+
+        let irp = self.irp_mut();
+
+        irp.IoStatus.__bindgen_anon_1.Status = status;
+
+        unsafe {
+            IoCompleteRequest(irp, IO_NO_INCREMENT as _);
+        }
+    }
+
+    /// Makes the lower device reuse the current stack location, for requests that are passed on
+    /// without a completion routine.
+    pub fn skip_current_stack_location(&self) {
+        unsafe {
+            IoSkipCurrentIrpStackLocation(self.irp);
+        }
+    }
+
+    /// Copies the current stack location to the next one, for requests that are passed on with a
+    /// completion routine.
+    pub fn copy_current_stack_location_to_next(&self) {
+        unsafe {
+            IoCopyCurrentIrpStackLocationToNext(self.irp);
+        }
+    }
+
+    /// Sets a completion routine on the next stack location. The routine is called with the
+    /// request and its final status once the lower device has completed it. It may run at
+    /// `DISPATCH_LEVEL`, which is why it is stored in non-paged pool.
+    pub fn set_completion_routine<F>(&self, routine: F) -> Result<(), Error>
+    where
+        F: FnOnce(&IoRequest, NTSTATUS) -> CompletionAction + Send + 'static,
+    {
+        let routine = Pool::new(routine, POOL_TYPE::NonPagedPoolNx, COMPLETION_TAG)
+            .ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        unsafe {
+            IoSetCompletionRoutine(
+                self.irp,
+                Some(completion_routine::<F>),
+                routine.into_raw() as PVOID,
+                1,
+                1,
+                1,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Sends the request to the lower device as set up in the next stack location. The request
+    /// is owned by the lower device afterwards.
+    pub fn call(self, lower: &LowerDevice) -> NTSTATUS {
+        unsafe { IoCallDriver(lower.as_raw_mut(), self.irp) }
+    }
+
+    /// Passes the request on to the lower device without a completion routine.
+    pub fn forward(self, lower: &LowerDevice) -> NTSTATUS {
+        self.skip_current_stack_location();
+        self.call(lower)
+    }
+
+    /// Passes the request on to the lower device and calls `routine` once the lower device has
+    /// completed it. The device is kept from being deleted until the routine has run. If the
+    /// device is being deleted or the completion routine could not be allocated, the request is
+    /// handed back in the error.
+    pub fn forward_with_completion<F>(
+        self,
+        lower: &LowerDevice,
+        routine: F,
+    ) -> Result<NTSTATUS, RequestError>
+    where
+        F: FnOnce(&IoRequest, NTSTATUS) -> CompletionAction + Send + 'static,
+    {
+        let lock = match RemoveLockGuard::acquire(&self) {
+            Ok(lock) => lock,
+            Err(e) => return Err(RequestError(e, self)),
+        };
+
+        let routine = move |request: &IoRequest, status| {
+            let action = routine(request, status);
+            drop(lock);
+            action
+        };
+
+        self.copy_current_stack_location_to_next();
+
+        match self.set_completion_routine(routine) {
+            Ok(()) => Ok(self.call(lower)),
+            Err(e) => Err(RequestError(e, self)),
+        }
+    }
+
+    /// Passes the request on to the lower device and waits for the lower device to complete it.
+    /// The request still has to be completed by the caller afterwards. This can only be called
+    /// at `PASSIVE_LEVEL`.
+    pub fn forward_and_wait(&self, lower: &LowerDevice) -> NTSTATUS {
+        let mut event: KEVENT = unsafe { core::mem::zeroed() };
+
+        unsafe {
+            KeInitializeEvent(&mut event, EVENT_TYPE::NotificationEvent, 0);
+
+            IoCopyCurrentIrpStackLocationToNext(self.irp);
+            IoSetCompletionRoutine(
+                self.irp,
+                Some(signal_completion),
+                &mut event as *mut KEVENT as PVOID,
+                1,
+                1,
+                1,
+            );
+
+            let status = IoCallDriver(lower.as_raw_mut(), self.irp);
+
+            if status != STATUS_PENDING {
+                return status;
+            }
+
+            KeWaitForSingleObject(
+                &mut event as *mut KEVENT as _,
+                KWAIT_REASON::Executive,
+                MODE::KernelMode as _,
+                0,
+                core::ptr::null_mut(),
+            );
+        }
+
+        self.status()
+    }
+
+    /// Completes the request with the given status, e.g. `Ok(size)` or `Err(e)`, without boosting
+    /// the priority of the thread that waits for it.
+    pub fn complete(self, status: impl Into<RequestStatus>) {
+        self.complete_with_boost(status, PriorityBoost::NO_INCREMENT);
+    }
+
+    /// Completes the request with the given status, and boosts the priority of the thread that
+    /// waits for it, e.g. [`PriorityBoost::KEYBOARD`] for a keyboard driver.
+    pub fn complete_with_boost(self, status: impl Into<RequestStatus>, boost: PriorityBoost) {
+        let status = status.into();
+
+        if status.ntstatus() < 0 {
+            release_failed_create(&self);
+        }
+
+        let irp = self.irp_mut();
+
+        irp.IoStatus.Information = status.information() as _;
+        irp.IoStatus.__bindgen_anon_1.Status = status.ntstatus();
+
+        unsafe {
+            IoCompleteRequest(irp, boost.0);
+        }
+    }
+}
+
+/// The status that a request is completed with. Every status but an error carries the value that
+/// is reported as `Information`, usually the number of bytes that were transferred. A warning such
+/// as `STATUS_BUFFER_OVERFLOW` reports the size of the partial data that was copied.
+///
+/// A status is built from a `Result`, or with [`RequestStatus::from_ntstatus`], which checks the
+/// severity of the `NTSTATUS`, so errors never report information.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestStatus {
+    status: NTSTATUS,
+    information: usize,
+}
+
+impl RequestStatus {
+    /// Builds the status by the severity of `status`. The information is dropped if `status` is
+    /// an error, i.e. `0xCXXXXXXX`.
+    pub fn from_ntstatus(status: NTSTATUS, information: usize) -> Self {
+        match (status as u32) >> 30 {
+            0..=2 => Self {
+                status,
+                information,
+            },
+            _ => Self::from(Error::from_ntstatus(status)),
+        }
+    }
+
+    pub fn ntstatus(&self) -> NTSTATUS {
+        self.status
+    }
+
+    pub fn information(&self) -> usize {
+        self.information
+    }
+}
+
+impl From<Result<usize, Error>> for RequestStatus {
+    fn from(result: Result<usize, Error>) -> Self {
+        match result {
+            Ok(information) => Self {
+                status: STATUS_SUCCESS,
+                information,
+            },
+            Err(error) => Self::from(error),
+        }
+    }
+}
+
+impl From<Error> for RequestStatus {
+    fn from(error: Error) -> Self {
+        Self {
+            status: error.to_ntstatus(),
+            information: 0,
+        }
+    }
+}
+
+/// The priority boost that the thread waiting for a request gets when the request is completed,
+/// to make devices that users interact with more responsive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PriorityBoost(pub i8);
+
+impl PriorityBoost {
+    pub const NO_INCREMENT: PriorityBoost = PriorityBoost(IO_NO_INCREMENT as _);
+    pub const CD_ROM: PriorityBoost = PriorityBoost(IO_CD_ROM_INCREMENT as _);
+    pub const DISK: PriorityBoost = PriorityBoost(IO_DISK_INCREMENT as _);
+    pub const KEYBOARD: PriorityBoost = PriorityBoost(IO_KEYBOARD_INCREMENT as _);
+    pub const MAILSLOT: PriorityBoost = PriorityBoost(IO_MAILSLOT_INCREMENT as _);
+    pub const MOUSE: PriorityBoost = PriorityBoost(IO_MOUSE_INCREMENT as _);
+    pub const NAMED_PIPE: PriorityBoost = PriorityBoost(IO_NAMED_PIPE_INCREMENT as _);
+    pub const NETWORK: PriorityBoost = PriorityBoost(IO_NETWORK_INCREMENT as _);
+    pub const PARALLEL: PriorityBoost = PriorityBoost(IO_PARALLEL_INCREMENT as _);
+    pub const SERIAL: PriorityBoost = PriorityBoost(IO_SERIAL_INCREMENT as _);
+    pub const SOUND: PriorityBoost = PriorityBoost(IO_SOUND_INCREMENT as _);
+    pub const VIDEO: PriorityBoost = PriorityBoost(IO_VIDEO_INCREMENT as _);
+}
+
+impl Default for PriorityBoost {
+    fn default() -> Self {
+        Self::NO_INCREMENT
+    }
+}
+
+pub(crate) unsafe extern "C" fn signal_completion(
+    _device: *mut DEVICE_OBJECT,
+    irp: *mut IRP,
+    context: PVOID,
+) -> NTSTATUS {
+    if (*irp).PendingReturned != 0 {
+        KeSetEvent(context as *mut KEVENT, IO_NO_INCREMENT as _, 0);
+    }
+
+    // The request is handed back to the dispatch routine that is waiting for it.
+    STATUS_MORE_PROCESSING_REQUIRED
+}
+
+/// What the I/O manager should do with a request after its completion routine has run.
+pub enum CompletionAction {
+    /// Continue completing the request, passing it on to the completion routines of the upper
+    /// devices.
+    Continue,
+    /// Stop completing the request. The driver has taken ownership of the request again, e.g. by
+    /// keeping its raw IRP pointer, and has to complete it once more later on.
+    MoreProcessingRequired,
+}
+
+unsafe extern "C" fn completion_routine<F>(
+    _device: *mut DEVICE_OBJECT,
+    irp: *mut IRP,
+    context: PVOID,
+) -> NTSTATUS
+where
+    F: FnOnce(&IoRequest, NTSTATUS) -> CompletionAction,
+{
+    let routine = Pool::from_raw(context as *mut F, COMPLETION_TAG).into_inner();
+    let request = IoRequest::from_raw(irp);
+
+    match routine(&request, request.status()) {
+        CompletionAction::Continue => {
+            // Propagate the pending state of the lower device up the stack.
+            if (*irp).PendingReturned != 0 {
+                IoMarkIrpPending(irp);
+            }
+
+            STATUS_SUCCESS
+        }
+        CompletionAction::MoreProcessingRequired => STATUS_MORE_PROCESSING_REQUIRED,
+    }
+}
+
+impl AsRef<IoRequest> for IoRequest {
+    fn as_ref(&self) -> &IoRequest {
+        self
+    }
+}
+
+/// Proof that a request has been marked pending, which the dispatch routine returns through
+/// [`Completion::Pending`]. It is only handed out by [`PendingRequest::new`] and
+/// [`IrpQueue::insert`], so a dispatch routine cannot return `STATUS_PENDING` for a request that
+/// was never marked pending.
+///
+/// [`Completion::Pending`]: crate::device::Completion::Pending
+#[must_use]
+pub struct PendingToken {
+    _private: (),
+}
+
+/// An I/O request that has been marked pending, such that it can be parked and completed at a
+/// later point from a worker thread, a timer or a DPC.
+///
+/// A [`PendingRequest`] owns the IRP: completing it consumes the handle, which makes it impossible
+/// to complete the same IRP twice. Dropping the handle without completing it is a bug that will
+/// panic in debug builds. In release builds the IRP is completed with `STATUS_CANCELLED` instead,
+/// so that the thread waiting for it does not hang forever.
+///
+/// The handle holds the remove lock of the device until the request is completed, so a device
+/// that is being removed or deleted waits for its pending requests. They have to be completed in
+/// [`PnpOperations::remove`] or before the device is dropped.
+///
+/// [`PnpOperations::remove`]: crate::pnp::PnpOperations::remove
+pub struct PendingRequest<R: Into<IoRequest> = IoRequest> {
+    request: Option<R>,
+    _lock: RemoveLockGuard,
+}
+
+unsafe impl<R: Into<IoRequest>> Send for PendingRequest<R> {}
+
+impl<R: Into<IoRequest> + AsRef<IoRequest>> PendingRequest<R> {
+    /// Marks the request as pending. The dispatch routine that received the request must then
+    /// return [`Completion::Pending`] with the returned token. If the device is being deleted,
+    /// the request is handed back in the error instead.
+    ///
+    /// [`Completion::Pending`]: crate::device::Completion::Pending
+    pub fn new(request: R) -> Result<(Self, PendingToken), RequestError> {
+        let lock = match RemoveLockGuard::acquire(request.as_ref()) {
+            Ok(lock) => lock,
+            Err(e) => return Err(RequestError(e, request.into())),
+        };
+
+        unsafe {
+            IoMarkIrpPending(request.as_ref().irp);
+        }
+
+        let pending = Self {
+            request: Some(request),
+            _lock: lock,
+        };
+
+        Ok((pending, PendingToken { _private: () }))
+    }
+}
+
+impl<R: Into<IoRequest>> PendingRequest<R> {
+    /// Wraps a request that has already been marked pending, e.g. by `IoCsqInsertIrp`, together
+    /// with the remove lock reference that was acquired for it.
+    pub(crate) unsafe fn from_marked(request: R, lock: RemoveLockGuard) -> Self {
+        Self {
+            request: Some(request),
+            _lock: lock,
+        }
+    }
+
+    /// Completes the pending request with the given status.
+    pub fn complete(self, status: impl Into<RequestStatus>) {
+        self.complete_with_boost(status, PriorityBoost::NO_INCREMENT);
+    }
+
+    /// Completes the pending request with the given status and priority boost.
+    pub fn complete_with_boost(mut self, status: impl Into<RequestStatus>, boost: PriorityBoost) {
+        if let Some(request) = self.request.take() {
+            request.into().complete_with_boost(status, boost);
+        }
+    }
+}
+
+impl<R: Into<IoRequest>> Deref for PendingRequest<R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        self.request.as_ref().unwrap()
+    }
+}
+
+impl<R: Into<IoRequest>> DerefMut for PendingRequest<R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.request.as_mut().unwrap()
+    }
+}
+
+impl<R: Into<IoRequest>> Drop for PendingRequest<R> {
+    fn drop(&mut self) {
+        if let Some(request) = self.request.take() {
+            debug_assert!(false, "pending request dropped without being completed");
+
+            request.into().complete(Err(Error::CANCELLED));
+        }
+    }
+}
+
+pub struct ReadRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for ReadRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl ReadRequest {
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let stack_location = self.stack_location();
+        let irp = self.irp();
+
+        let buffer = if !irp.MdlAddress.is_null() {
+            let ptr = unsafe {
+                MmGetSystemAddressForMdlSafe(
+                    irp.MdlAddress,
+                    MM_PAGE_PRIORITY::HighPagePriority as _,
+                )
+            };
+
+            let size = unsafe { MmGetMdlByteCount(irp.MdlAddress) } as usize;
+
+            Buffer::mapped(ptr, size)
+        } else if !unsafe { irp.AssociatedIrp.SystemBuffer }.is_null() {
+            let ptr = unsafe { irp.AssociatedIrp.SystemBuffer };
+            let size = unsafe { stack_location.Parameters.Read }.Length as usize;
+
+            Buffer::system(ptr, size)
+        } else {
+            Buffer::empty()
+        };
+
+        unsafe { UserPtr::new(Buffer::empty(), buffer) }
+    }
+
+    /// Returns the position in the file that the data is to be read from. If the caller did not
+    /// pass a position, this is the current position of the file object.
+    pub fn byte_offset(&self) -> i64 {
+        let offset = unsafe { self.stack_location().Parameters.Read.ByteOffset.QuadPart };
+
+        file_byte_offset(self, offset)
+    }
+
+    /// Moves the current position of the file object past the `size` bytes that were read, like a
+    /// file system does for files that were opened for synchronous I/O. Call this before completing
+    /// the request successfully.
+    pub fn advance_file_position(&self, size: usize) {
+        advance_file_position(self, self.byte_offset(), size);
+    }
+}
+
+impl Into<IoRequest> for ReadRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for ReadRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct WriteRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for WriteRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl WriteRequest {
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let stack_location = self.stack_location();
+        let irp = self.irp();
+
+        let buffer = if !irp.MdlAddress.is_null() {
+            let ptr = unsafe {
+                MmGetSystemAddressForMdlSafe(
+                    irp.MdlAddress,
+                    MM_PAGE_PRIORITY::HighPagePriority as _,
+                )
+            };
+
+            let size = unsafe { MmGetMdlByteCount(irp.MdlAddress) } as usize;
+
+            Buffer::mapped(ptr, size)
+        } else if !unsafe { irp.AssociatedIrp.SystemBuffer }.is_null() {
+            let ptr = unsafe { irp.AssociatedIrp.SystemBuffer };
+            let size = unsafe { stack_location.Parameters.Write }.Length as usize;
+
+            Buffer::system(ptr, size)
+        } else {
+            Buffer::empty()
+        };
+
+        unsafe { UserPtr::new(buffer, Buffer::empty()) }
+    }
+
+    /// Returns the position in the file that the data is to be written to. If the caller did not
+    /// pass a position, this is the current position of the file object.
+    pub fn byte_offset(&self) -> i64 {
+        let offset = unsafe { self.stack_location().Parameters.Write.ByteOffset.QuadPart };
+
+        file_byte_offset(self, offset)
+    }
+
+    /// Moves the current position of the file object past the `size` bytes that were written, like
+    /// a file system does for files that were opened for synchronous I/O. Call this before
+    /// completing the request successfully.
+    pub fn advance_file_position(&self, size: usize) {
+        advance_file_position(self, self.byte_offset(), size);
+    }
+}
+
+impl Into<IoRequest> for WriteRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for WriteRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct IoControlRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for IoControlRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl IoControlRequest {
+    pub fn control_code(&self) -> ControlCode {
+        let stack_location = self.stack_location();
+
+        unsafe {
+            stack_location
+                .Parameters
+                .DeviceIoControl
+                .IoControlCode
+                .into()
+        }
+    }
+
+    pub fn function(&self) -> (RequiredAccess, u32) {
+        let code = self.control_code();
+
+        (code.required_access(), code.number())
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let stack_location = self.stack_location();
+
+        let input_size =
+            unsafe { stack_location.Parameters.DeviceIoControl.InputBufferLength } as usize;
+        let output_size =
+            unsafe { stack_location.Parameters.DeviceIoControl.OutputBufferLength } as usize;
+
+        control_user_ptr(
+            self,
+            self.control_code().transfer_method(),
+            input_size,
+            output_size,
+        )
+    }
+}
+
+/// The byte offset that tells a read or write request to use the current position of the file
+/// object.
+const USE_FILE_POINTER_POSITION: i64 = (-1i64 << 32) | FILE_USE_FILE_POINTER_POSITION as i64;
+
+fn file_byte_offset(request: &IoRequest, offset: i64) -> i64 {
+    let file_object = request.file_object();
+
+    if offset != USE_FILE_POINTER_POSITION || file_object.is_null() {
+        return offset;
+    }
+
+    unsafe { (*file_object).CurrentByteOffset.QuadPart }
+}
+
+fn advance_file_position(request: &IoRequest, offset: i64, size: usize) {
+    let file_object = request.file_object();
+
+    if file_object.is_null() || unsafe { (*file_object).Flags } & FO_SYNCHRONOUS_IO == 0 {
+        return;
+    }
+
+    unsafe {
+        (*file_object).CurrentByteOffset.QuadPart = offset.saturating_add(size as i64);
+    }
+}
+
+fn control_user_ptr<'a>(
+    request: &'a IoRequest,
+    transfer_method: TransferMethod,
+    input_size: usize,
+    output_size: usize,
+) -> UserPtr<'a> {
+    let irp = request.irp();
+
+    let system_buffer = unsafe { irp.AssociatedIrp.SystemBuffer };
+
+    let mdl_address = if !irp.MdlAddress.is_null() {
+        unsafe {
+            MmGetSystemAddressForMdlSafe(irp.MdlAddress, MM_PAGE_PRIORITY::HighPagePriority as _)
+        }
+    } else {
+        core::ptr::null_mut()
+    };
+
+    match transfer_method {
+        TransferMethod::Buffered => unsafe {
+            UserPtr::new_buffered(system_buffer, input_size, output_size)
+        },
+        TransferMethod::InputDirect => unsafe {
+            UserPtr::new(
+                Buffer::mapped(mdl_address, output_size),
+                Buffer::system(system_buffer, input_size),
+            )
+        },
+        TransferMethod::OutputDirect => unsafe {
+            UserPtr::new(
+                Buffer::system(system_buffer, input_size),
+                Buffer::mapped(mdl_address, output_size),
+            )
+        },
+        TransferMethod::Neither => unsafe {
+            let input = request
+                .stack_location()
+                .Parameters
+                .DeviceIoControl
+                .Type3InputBuffer;
+
+            let probe = irp.RequestorMode as MODE::Type == MODE::UserMode;
+
+            UserPtr::new(
+                Buffer::neither(input, input_size, probe),
+                Buffer::neither(irp.UserBuffer, output_size, probe),
+            )
+        },
+    }
+}
+
+impl Into<IoRequest> for IoControlRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for IoControlRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct QueryInformationRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for QueryInformationRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl QueryInformationRequest {
+    pub fn file_information_class(&self) -> FILE_INFORMATION_CLASS {
+        unsafe {
+            self.stack_location()
+                .Parameters
+                .QueryFile
+                .FileInformationClass
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        unsafe { self.stack_location().Parameters.QueryFile.Length as usize }
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let system_buffer = unsafe { self.irp().AssociatedIrp.SystemBuffer };
+
+        unsafe { UserPtr::new_buffered(system_buffer, 0, self.length()) }
+    }
+}
+
+impl Into<IoRequest> for QueryInformationRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for QueryInformationRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct SetInformationRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for SetInformationRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl SetInformationRequest {
+    pub fn file_information_class(&self) -> FILE_INFORMATION_CLASS {
+        unsafe {
+            self.stack_location()
+                .Parameters
+                .SetFile
+                .FileInformationClass
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        unsafe { self.stack_location().Parameters.SetFile.Length as usize }
+    }
+
+    /// The target file object of a rename or link operation, if any.
+    pub fn target_file_object(&self) -> *mut FILE_OBJECT {
+        unsafe { self.stack_location().Parameters.SetFile.FileObject }
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let system_buffer = unsafe { self.irp().AssociatedIrp.SystemBuffer };
+
+        unsafe { UserPtr::new_buffered(system_buffer, self.length(), 0) }
+    }
+}
+
+impl Into<IoRequest> for SetInformationRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for SetInformationRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct FlushBuffersRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for FlushBuffersRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Into<IoRequest> for FlushBuffersRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for FlushBuffersRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct ShutdownRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for ShutdownRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Into<IoRequest> for ShutdownRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for ShutdownRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct InternalIoControlRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for InternalIoControlRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl InternalIoControlRequest {
+    pub fn control_code(&self) -> ControlCode {
+        let stack_location = self.stack_location();
+
+        unsafe {
+            stack_location
+                .Parameters
+                .DeviceIoControl
+                .IoControlCode
+                .into()
+        }
+    }
+
+    pub fn function(&self) -> (RequiredAccess, u32) {
+        let code = self.control_code();
+
+        (code.required_access(), code.number())
+    }
+
+    /// The four driver-defined arguments of the request. Internal device control requests are
+    /// frequently used to pass pointers between drivers through these rather than through
+    /// buffers.
+    pub fn arguments(&self) -> [PVOID; 4] {
+        let others = unsafe { self.stack_location().Parameters.Others };
+
+        [
+            others.Argument1,
+            others.Argument2,
+            others.Argument3,
+            others.Argument4,
+        ]
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let stack_location = self.stack_location();
+
+        let input_size =
+            unsafe { stack_location.Parameters.DeviceIoControl.InputBufferLength } as usize;
+        let output_size =
+            unsafe { stack_location.Parameters.DeviceIoControl.OutputBufferLength } as usize;
+
+        control_user_ptr(
+            self,
+            self.control_code().transfer_method(),
+            input_size,
+            output_size,
+        )
+    }
+}
+
+impl Into<IoRequest> for InternalIoControlRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for InternalIoControlRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct FileSystemControlRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for FileSystemControlRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl FileSystemControlRequest {
+    pub fn control_code(&self) -> ControlCode {
+        let stack_location = self.stack_location();
+
+        unsafe {
+            stack_location
+                .Parameters
+                .FileSystemControl
+                .FsControlCode
+                .into()
+        }
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let stack_location = self.stack_location();
+
+        let input_size = unsafe {
+            stack_location
+                .Parameters
+                .FileSystemControl
+                .InputBufferLength
+        } as usize;
+        let output_size = unsafe {
+            stack_location
+                .Parameters
+                .FileSystemControl
+                .OutputBufferLength
+        } as usize;
+
+        control_user_ptr(
+            self,
+            self.control_code().transfer_method(),
+            input_size,
+            output_size,
+        )
+    }
+}
+
+impl Into<IoRequest> for FileSystemControlRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for FileSystemControlRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LockOperation {
+    Lock,
+    UnlockSingle,
+    UnlockAll,
+    UnlockAllByKey,
+}
+
+pub struct LockControlRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for LockControlRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl LockControlRequest {
+    pub fn operation(&self) -> Option<LockOperation> {
+        match self.minor() as _ {
+            IRP_MN_LOCK => Some(LockOperation::Lock),
+            IRP_MN_UNLOCK_SINGLE => Some(LockOperation::UnlockSingle),
+            IRP_MN_UNLOCK_ALL => Some(LockOperation::UnlockAll),
+            IRP_MN_UNLOCK_ALL_BY_KEY => Some(LockOperation::UnlockAllByKey),
+            _ => None,
+        }
+    }
+
+    pub fn byte_offset(&self) -> i64 {
+        unsafe {
+            self.stack_location()
+                .Parameters
+                .LockControl
+                .ByteOffset
+                .QuadPart
+        }
+    }
+
+    pub fn length(&self) -> i64 {
+        let length = unsafe { self.stack_location().Parameters.LockControl.Length };
+
+        if length.is_null() {
+            0
+        } else {
+            unsafe { (*length).QuadPart }
+        }
+    }
+
+    pub fn key(&self) -> u32 {
+        unsafe { self.stack_location().Parameters.LockControl.Key }
+    }
+}
+
+impl Into<IoRequest> for LockControlRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for LockControlRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DirectoryOperation {
+    QueryDirectory,
+    NotifyChangeDirectory,
+}
+
+pub struct DirectoryControlRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for DirectoryControlRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DirectoryControlRequest {
+    pub fn operation(&self) -> Option<DirectoryOperation> {
+        match self.minor() as _ {
+            IRP_MN_QUERY_DIRECTORY => Some(DirectoryOperation::QueryDirectory),
+            IRP_MN_NOTIFY_CHANGE_DIRECTORY => Some(DirectoryOperation::NotifyChangeDirectory),
+            _ => None,
+        }
+    }
+
+    /// The length of the output buffer, for both queries and change notifications.
+    pub fn length(&self) -> usize {
+        match self.operation() {
+            Some(DirectoryOperation::NotifyChangeDirectory) => unsafe {
+                self.stack_location().Parameters.NotifyDirectory.Length as usize
+            },
+            _ => unsafe { self.stack_location().Parameters.QueryDirectory.Length as usize },
+        }
+    }
+
+    /// The search pattern of a directory query, if any.
+    pub fn file_name(&self) -> Option<&UNICODE_STRING> {
+        if self.operation() != Some(DirectoryOperation::QueryDirectory) {
+            return None;
+        }
+
+        unsafe {
+            self.stack_location()
+                .Parameters
+                .QueryDirectory
+                .FileName
+                .as_ref()
+        }
+    }
+
+    pub fn file_information_class(&self) -> Option<FILE_INFORMATION_CLASS> {
+        match self.operation() {
+            Some(DirectoryOperation::QueryDirectory) => unsafe {
+                Some(
+                    self.stack_location()
+                        .Parameters
+                        .QueryDirectory
+                        .FileInformationClass,
+                )
+            },
+            _ => None,
+        }
+    }
+
+    pub fn file_index(&self) -> Option<u32> {
+        match self.operation() {
+            Some(DirectoryOperation::QueryDirectory) => unsafe {
+                Some(self.stack_location().Parameters.QueryDirectory.FileIndex)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn completion_filter(&self) -> Option<u32> {
+        match self.operation() {
+            Some(DirectoryOperation::NotifyChangeDirectory) => unsafe {
+                Some(
+                    self.stack_location()
+                        .Parameters
+                        .NotifyDirectory
+                        .CompletionFilter,
+                )
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Into<IoRequest> for DirectoryControlRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for DirectoryControlRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
+
+pub struct QueryVolumeInformationRequest {
+    pub(crate) inner: IoRequest,
+}
+
+impl Deref for QueryVolumeInformationRequest {
+    type Target = IoRequest;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl QueryVolumeInformationRequest {
+    pub fn fs_information_class(&self) -> FS_INFORMATION_CLASS {
+        unsafe {
+            self.stack_location()
+                .Parameters
+                .QueryVolume
+                .FsInformationClass
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        unsafe { self.stack_location().Parameters.QueryVolume.Length as usize }
+    }
+
+    pub fn user_ptr(&mut self) -> UserPtr<'_> {
+        let system_buffer = unsafe { self.irp().AssociatedIrp.SystemBuffer };
+
+        unsafe { UserPtr::new_buffered(system_buffer, 0, self.length()) }
+    }
+}
+
+impl Into<IoRequest> for QueryVolumeInformationRequest {
+    fn into(self) -> IoRequest {
+        self.inner
+    }
+}
+
+impl AsRef<IoRequest> for QueryVolumeInformationRequest {
+    fn as_ref(&self) -> &IoRequest {
+        &self.inner
+    }
+}
@@ -0,0 +1,320 @@
+//! This module provides a cancel-safe queue to park pending I/O requests in.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut, null_mut};
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use wdk_sys::base::{
+    FILE_OBJECT, IO_CSQ, IO_NO_INCREMENT, IRP, IRP_MJ_DEVICE_CONTROL, IRP_MJ_READ, IRP_MJ_WRITE,
+    KEVENT, KIRQL, KSPIN_LOCK, LIST_ENTRY, PIRP, PKIRQL, PVOID, _EVENT_TYPE as EVENT_TYPE,
+    _KWAIT_REASON as KWAIT_REASON, _MODE as MODE, _POOL_TYPE as POOL_TYPE,
+};
+use wdk_sys::ntoskrnl::{
+    IoCsqInitialize, IoCsqInsertIrp, IoCsqRemoveNextIrp, IoGetCurrentIrpStackLocation,
+    KeAcquireSpinLock, KeInitializeEvent, KeReleaseSpinLock, KeSetEvent, KeWaitForSingleObject,
+};
+
+use crate::allocator::Pool;
+use crate::device::{RemoveLockGuard, RequestError};
+use crate::error::{Error, IntoResult};
+use crate::request::{
+    IoControlRequest, IoRequest, PendingRequest, PendingToken, PriorityBoost, ReadRequest,
+    RequestStatus, WriteRequest,
+};
+
+const QUEUE_TAG: u32 = u32::from_ne_bytes(*b"rcsq");
+
+#[repr(C)]
+struct QueueInner {
+    csq: IO_CSQ,
+    lock: KSPIN_LOCK,
+    list: LIST_ENTRY,
+    /// The number of requests that are queued or being cancelled, plus one for the queue itself.
+    outstanding: AtomicI32,
+    /// Signalled once `outstanding` drops to zero.
+    event: KEVENT,
+}
+
+/// A request that has been removed from an [`IrpQueue`]. The request is still pending and has to
+/// be completed by the driver.
+pub enum QueuedRequest {
+    Read(PendingRequest<ReadRequest>),
+    Write(PendingRequest<WriteRequest>),
+    IoControl(PendingRequest<IoControlRequest>),
+    Other(PendingRequest<IoRequest>),
+}
+
+impl QueuedRequest {
+    unsafe fn from_raw(irp: PIRP) -> Self {
+        let request = IoRequest::from_raw(irp);
+        // The reference was acquired when the request was inserted.
+        let lock = RemoveLockGuard::adopt(&request);
+
+        match request.major() as _ {
+            IRP_MJ_READ => Self::Read(PendingRequest::from_marked(
+                ReadRequest { inner: request },
+                lock,
+            )),
+            IRP_MJ_WRITE => Self::Write(PendingRequest::from_marked(
+                WriteRequest { inner: request },
+                lock,
+            )),
+            IRP_MJ_DEVICE_CONTROL => Self::IoControl(PendingRequest::from_marked(
+                IoControlRequest { inner: request },
+                lock,
+            )),
+            _ => Self::Other(PendingRequest::from_marked(request, lock)),
+        }
+    }
+
+    /// Completes the request with the given status.
+    pub fn complete(self, status: impl Into<RequestStatus>) {
+        self.complete_with_boost(status, PriorityBoost::NO_INCREMENT);
+    }
+
+    /// Completes the request with the given status and priority boost.
+    pub fn complete_with_boost(self, status: impl Into<RequestStatus>, boost: PriorityBoost) {
+        match self {
+            Self::Read(request) => request.complete_with_boost(status, boost),
+            Self::Write(request) => request.complete_with_boost(status, boost),
+            Self::IoControl(request) => request.complete_with_boost(status, boost),
+            Self::Other(request) => request.complete_with_boost(status, boost),
+        }
+    }
+}
+
+/// A queue of pending I/O requests built on top of the Cancel-Safe Queue (`IoCsq*`) routines.
+///
+/// Requests that are inserted into the queue are marked pending and the dispatch routine has to
+/// return [`Completion::Pending`]. If the I/O manager cancels a request while it is queued, e.g.
+/// because the user process exits, the request is removed from the queue and completed with
+/// `STATUS_CANCELLED` automatically. Any requests that are still queued when the queue is dropped
+/// are cancelled as well.
+///
+/// Queued requests hold the remove lock of their device, like a [`PendingRequest`] does, so the
+/// queue has to be flushed before the device is removed or deleted. Dropping the queue waits for
+/// requests that the I/O manager is still cancelling, so it must happen at `PASSIVE_LEVEL`.
+///
+/// The queue lives in non-paged pool, as the queue callbacks run with a spin lock held.
+///
+/// [`Completion::Pending`]: crate::device::Completion::Pending
+pub struct IrpQueue {
+    inner: Pool<UnsafeCell<QueueInner>>,
+}
+
+unsafe impl Send for IrpQueue {}
+unsafe impl Sync for IrpQueue {}
+
+impl IrpQueue {
+    /// Creates a new, empty queue.
+    pub fn new() -> Result<Self, Error> {
+        let inner = Pool::new(
+            UnsafeCell::new(unsafe { core::mem::zeroed::<QueueInner>() }),
+            POOL_TYPE::NonPagedPoolNx,
+            QUEUE_TAG,
+        )
+        .ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        let queue = inner.get();
+
+        unsafe {
+            let list = addr_of_mut!((*queue).list);
+            (*list).Flink = list;
+            (*list).Blink = list;
+
+            (*queue).outstanding = AtomicI32::new(1);
+            KeInitializeEvent(
+                addr_of_mut!((*queue).event),
+                EVENT_TYPE::NotificationEvent,
+                0,
+            );
+
+            IoCsqInitialize(
+                addr_of_mut!((*queue).csq),
+                Some(csq_insert_irp),
+                Some(csq_remove_irp),
+                Some(csq_peek_next_irp),
+                Some(csq_acquire_lock),
+                Some(csq_release_lock),
+                Some(csq_complete_canceled_irp),
+            )
+        }
+        .into_result()?;
+
+        Ok(Self { inner })
+    }
+
+    fn csq(&self) -> *mut IO_CSQ {
+        unsafe { addr_of_mut!((*self.inner.get()).csq) }
+    }
+
+    /// Marks the request pending and inserts it at the tail of the queue. If the request has
+    /// already been cancelled, it is completed with `STATUS_CANCELLED` right away. Either way the
+    /// dispatch routine has to return [`Completion::Pending`] with the returned token. If the
+    /// device is being deleted, the request is handed back in the error instead.
+    ///
+    /// [`Completion::Pending`]: crate::device::Completion::Pending
+    pub fn insert<R: Into<IoRequest>>(&self, request: R) -> Result<PendingToken, RequestError> {
+        let request = request.into();
+
+        let lock = match RemoveLockGuard::acquire(&request) {
+            Ok(lock) => lock,
+            Err(e) => return Err(RequestError(e, request)),
+        };
+
+        // The reference is taken over again when the request leaves the queue.
+        core::mem::forget(lock);
+
+        unsafe {
+            IoCsqInsertIrp(self.csq(), request.irp, null_mut());
+        }
+
+        Ok(PendingToken { _private: () })
+    }
+
+    /// Removes the request at the head of the queue, if any.
+    pub fn remove_next(&self) -> Option<QueuedRequest> {
+        let irp = unsafe { IoCsqRemoveNextIrp(self.csq(), null_mut()) };
+
+        self.removed(irp)
+    }
+
+    /// Removes the first request in the queue that was issued on the given file object, if any.
+    /// This is typically used to flush the requests of a handle that is being cleaned up.
+    pub fn remove_next_for_file(&self, file_object: *mut FILE_OBJECT) -> Option<QueuedRequest> {
+        let irp = unsafe { IoCsqRemoveNextIrp(self.csq(), file_object as PVOID) };
+
+        self.removed(irp)
+    }
+
+    fn removed(&self, irp: PIRP) -> Option<QueuedRequest> {
+        if irp.is_null() {
+            return None;
+        }
+
+        unsafe {
+            release_outstanding(self.inner.get());
+
+            Some(QueuedRequest::from_raw(irp))
+        }
+    }
+}
+
+impl Drop for IrpQueue {
+    fn drop(&mut self) {
+        while let Some(request) = self.remove_next() {
+            request.complete(Err(Error::CANCELLED));
+        }
+
+        // Requests that were cancelled but whose cancellation has not finished yet still
+        // reference the queue, so wait for them before the queue is freed.
+        let queue = self.inner.get();
+
+        unsafe {
+            if (*queue).outstanding.fetch_sub(1, Ordering::AcqRel) != 1 {
+                KeWaitForSingleObject(
+                    addr_of_mut!((*queue).event) as _,
+                    KWAIT_REASON::Executive,
+                    MODE::KernelMode as _,
+                    0,
+                    null_mut(),
+                );
+            }
+        }
+    }
+}
+
+unsafe fn release_outstanding(queue: *mut QueueInner) {
+    if (*queue).outstanding.fetch_sub(1, Ordering::AcqRel) == 1 {
+        KeSetEvent(addr_of_mut!((*queue).event), IO_NO_INCREMENT as _, 0);
+    }
+}
+
+fn list_entry_offset() -> usize {
+    let irp = MaybeUninit::<IRP>::uninit();
+    let base = irp.as_ptr();
+
+    unsafe { addr_of!((*base).Tail.Overlay.__bindgen_anon_2.ListEntry) as usize - base as usize }
+}
+
+unsafe fn irp_list_entry(irp: PIRP) -> *mut LIST_ENTRY {
+    addr_of_mut!((*irp).Tail.Overlay.__bindgen_anon_2.ListEntry)
+}
+
+unsafe fn irp_from_list_entry(entry: *mut LIST_ENTRY) -> PIRP {
+    (entry as usize - list_entry_offset()) as PIRP
+}
+
+unsafe fn queue_from_csq(csq: *mut IO_CSQ) -> *mut QueueInner {
+    // The IO_CSQ is the first field of the repr(C) QueueInner.
+    csq as *mut QueueInner
+}
+
+unsafe extern "C" fn csq_insert_irp(csq: *mut IO_CSQ, irp: PIRP) {
+    let queue = queue_from_csq(csq);
+    (*queue).outstanding.fetch_add(1, Ordering::AcqRel);
+
+    let list = addr_of_mut!((*queue).list);
+    let entry = irp_list_entry(irp);
+    let tail = (*list).Blink;
+
+    (*entry).Flink = list;
+    (*entry).Blink = tail;
+    (*tail).Flink = entry;
+    (*list).Blink = entry;
+}
+
+unsafe extern "C" fn csq_remove_irp(_csq: *mut IO_CSQ, irp: PIRP) {
+    let entry = irp_list_entry(irp);
+    let next = (*entry).Flink;
+    let prev = (*entry).Blink;
+
+    (*prev).Flink = next;
+    (*next).Blink = prev;
+    (*entry).Flink = entry;
+    (*entry).Blink = entry;
+}
+
+unsafe extern "C" fn csq_peek_next_irp(csq: *mut IO_CSQ, irp: PIRP, peek_context: PVOID) -> PIRP {
+    let list = addr_of_mut!((*queue_from_csq(csq)).list);
+
+    let mut entry = if irp.is_null() {
+        (*list).Flink
+    } else {
+        (*irp_list_entry(irp)).Flink
+    };
+
+    while entry != list {
+        let next = irp_from_list_entry(entry);
+
+        if peek_context.is_null()
+            || (*IoGetCurrentIrpStackLocation(next)).FileObject as PVOID == peek_context
+        {
+            return next;
+        }
+
+        entry = (*entry).Flink;
+    }
+
+    null_mut()
+}
+
+unsafe extern "C" fn csq_acquire_lock(csq: *mut IO_CSQ, irql: PKIRQL) {
+    KeAcquireSpinLock(addr_of_mut!((*queue_from_csq(csq)).lock), irql);
+}
+
+unsafe extern "C" fn csq_release_lock(csq: *mut IO_CSQ, irql: KIRQL) {
+    KeReleaseSpinLock(addr_of_mut!((*queue_from_csq(csq)).lock), irql);
+}
+
+unsafe extern "C" fn csq_complete_canceled_irp(csq: *mut IO_CSQ, irp: PIRP) {
+    let request = IoRequest::from_raw(irp);
+    let lock = RemoveLockGuard::adopt(&request);
+
+    request.complete(Err(Error::CANCELLED));
+    drop(lock);
+
+    // This is the last time the queue is touched on behalf of the request.
+    release_outstanding(queue_from_csq(csq));
+}
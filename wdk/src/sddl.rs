@@ -0,0 +1,9 @@
+//! This module provides validated SDDL strings for the security descriptors of device objects, as
+//! accepted by `IoCreateDeviceSecure`. The validator lives in `wdk-ioctl`, which does not depend
+//! on the kernel.
+
+pub use wdk_ioctl::{
+    ParseSddlError, Sddl, SDDL_DEVOBJ_KERNEL_ONLY, SDDL_DEVOBJ_SYS_ALL,
+    SDDL_DEVOBJ_SYS_ALL_ADM_ALL, SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RWX_RES_RWX,
+    SDDL_DEVOBJ_SYS_ALL_ADM_RWX_WORLD_RW_RES_R, SDDL_DEVOBJ_SYS_ALL_ADM_RX,
+};
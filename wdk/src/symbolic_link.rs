@@ -9,6 +9,9 @@ pub enum SymbolicLink {
     ConstName { name: UNICODE_STRING },
 }
 
+// The name is only read again to delete the link, which can happen on any thread.
+unsafe impl Send for SymbolicLink {}
+
 impl SymbolicLink {
     pub fn new(name: UnicodeString, target: &UnicodeString) -> Result<Self, Error> {
         unsafe {
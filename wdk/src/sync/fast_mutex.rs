@@ -22,20 +22,20 @@ use crate::error::Error;
 /// [`lock`]: FastMutex::lock
 /// [`try_lock`]: FastMutex::try_lock
 pub struct FastMutex<T: ?Sized> {
-    pub(crate) lock: Box<FAST_MUTEX>,
+    pub(crate) lock: Box<UnsafeCell<FAST_MUTEX>>,
     pub(crate) data: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for FastMutex<T> {}
-unsafe impl<T> Sync for FastMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for FastMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for FastMutex<T> {}
 
 impl<T> FastMutex<T> {
     /// Creates a new mutex in an unlocked state ready for use.
     pub fn new(data: T) -> Result<Self, Error> {
-        let mut lock: Box<FAST_MUTEX> =
-            <Box<_> as FallibleBox<_>>::try_new(unsafe { core::mem::zeroed() })?;
+        let lock: Box<UnsafeCell<FAST_MUTEX>> =
+            <Box<_> as FallibleBox<_>>::try_new(UnsafeCell::new(unsafe { core::mem::zeroed() }))?;
 
-        unsafe { ExInitializeFastMutex(&mut *lock) };
+        unsafe { ExInitializeFastMutex(lock.get()) };
 
         Ok(Self {
             lock,
@@ -57,12 +57,12 @@ impl<T> FastMutex<T> {
     ///
     /// This function does not block.
     #[inline]
-    pub fn try_lock(&mut self) -> Option<FastMutexGuard<T>> {
-        let status = unsafe { ExTryToAcquireFastMutex(&mut *self.lock) } != 0;
+    pub fn try_lock(&self) -> Option<FastMutexGuard<T>> {
+        let status = unsafe { ExTryToAcquireFastMutex(self.lock.get()) } != 0;
 
         match status {
             true => Some(FastMutexGuard {
-                lock: &mut self.lock,
+                lock: &self.lock,
                 data: unsafe { &mut *self.data.get() },
             }),
             _ => None,
@@ -79,11 +79,11 @@ impl<T> FastMutex<T> {
     /// The underlying function does not allow for recursion. If the thread already holds the lock
     /// and tries to lock the mutex again, this function will return `None` instead.
     #[inline]
-    pub fn lock(&mut self) -> Option<FastMutexGuard<T>> {
-        unsafe { ExAcquireFastMutex(&mut *self.lock) };
+    pub fn lock(&self) -> Option<FastMutexGuard<T>> {
+        unsafe { ExAcquireFastMutex(self.lock.get()) };
 
         Some(FastMutexGuard {
-            lock: &mut self.lock,
+            lock: &self.lock,
             data: unsafe { &mut *self.data.get() },
         })
     }
@@ -112,13 +112,13 @@ impl<T> FastMutex<T> {
 /// [`lock`]: FastMutex::lock
 /// [`try_lock`]: FastMutex::try_lock
 pub struct FastMutexGuard<'a, T: 'a + ?Sized> {
-    pub(crate) lock: &'a mut FAST_MUTEX,
+    pub(crate) lock: &'a UnsafeCell<FAST_MUTEX>,
     pub(crate) data: &'a mut T,
 }
 
 impl<'a, T: ?Sized> Drop for FastMutexGuard<'a, T> {
     fn drop(&mut self) {
-        unsafe { ExReleaseFastMutex(&mut *self.lock) };
+        unsafe { ExReleaseFastMutex(self.lock.get()) };
     }
 }
 
@@ -36,19 +36,20 @@ use crate::error::Error;
 ///
 /// [`FastMutex`]: crate::fast_mutex::FastMutex
 pub struct PushLock<T: ?Sized> {
-    pub(crate) lock: Box<EX_PUSH_LOCK>,
+    pub(crate) lock: Box<UnsafeCell<EX_PUSH_LOCK>>,
     pub(crate) data: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for PushLock<T> {}
-unsafe impl<T> Sync for PushLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for PushLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for PushLock<T> {}
 
 impl<T> PushLock<T> {
     /// Creates new instance of [`PushLock<T>`] that is unlocked.
     pub fn new(data: T) -> Result<Self, Error> {
-        let mut lock: Box<EX_PUSH_LOCK> = <Box<_> as FallibleBox<_>>::try_new(0)?;
+        let lock: Box<UnsafeCell<EX_PUSH_LOCK>> =
+            <Box<_> as FallibleBox<_>>::try_new(UnsafeCell::new(0))?;
 
-        unsafe { ExInitializePushLock(&mut *lock) };
+        unsafe { ExInitializePushLock(lock.get()) };
 
         Ok(Self {
             lock,
@@ -77,14 +78,14 @@ impl<T> PushLock<T> {
     /// reader counter. Since dropping the RAII guard releases the lock by atomically decrementing
     /// this shared counter, it will eventually reach zero once all RAII guards have been dropped.
     #[inline]
-    pub fn read(&mut self) -> Option<PushLockReadGuard<T>> {
+    pub fn read(&self) -> Option<PushLockReadGuard<T>> {
         unsafe { KeEnterCriticalRegion() };
 
-        unsafe { ExAcquirePushLockShared(&mut *self.lock) };
+        unsafe { ExAcquirePushLockShared(self.lock.get()) };
 
         Some(PushLockReadGuard {
-            lock: &mut self.lock,
-            data: unsafe { &mut *self.data.get() },
+            lock: &self.lock,
+            data: unsafe { &*self.data.get() },
         })
     }
 
@@ -101,13 +102,13 @@ impl<T> PushLock<T> {
     ///
     /// The underlying function does not allow for recursion, which ensures correct behavior.
     #[inline]
-    pub fn write(&mut self) -> Option<PushLockWriteGuard<T>> {
+    pub fn write(&self) -> Option<PushLockWriteGuard<T>> {
         unsafe { KeEnterCriticalRegion() };
 
-        unsafe { ExAcquirePushLockExclusive(&mut *self.lock) };
+        unsafe { ExAcquirePushLockExclusive(self.lock.get()) };
 
         Some(PushLockWriteGuard {
-            lock: &mut self.lock,
+            lock: &self.lock,
             data: unsafe { &mut *self.data.get() },
         })
     }
@@ -120,13 +121,13 @@ impl<T> PushLock<T> {
 /// [`read`]: PushLock::read
 /// [`try_read`]: PushLock::try_read
 pub struct PushLockReadGuard<'a, T: 'a + ?Sized> {
-    pub(crate) lock: &'a mut EX_PUSH_LOCK,
+    pub(crate) lock: &'a UnsafeCell<EX_PUSH_LOCK>,
     pub(crate) data: &'a T,
 }
 
 impl<'a, T: ?Sized> Drop for PushLockReadGuard<'a, T> {
     fn drop(&mut self) {
-        unsafe { ExReleasePushLockShared(&mut *self.lock) };
+        unsafe { ExReleasePushLockShared(self.lock.get()) };
 
         unsafe { KeLeaveCriticalRegion() };
     }
@@ -147,13 +148,13 @@ impl<'a, T: ?Sized> Deref for PushLockReadGuard<'a, T> {
 /// [`write`]: PushLock::write
 /// [`try_write`]: PushLock::try_write
 pub struct PushLockWriteGuard<'a, T: 'a + ?Sized> {
-    pub(crate) lock: &'a mut EX_PUSH_LOCK,
+    pub(crate) lock: &'a UnsafeCell<EX_PUSH_LOCK>,
     pub(crate) data: &'a mut T,
 }
 
 impl<'a, T: ?Sized> Drop for PushLockWriteGuard<'a, T> {
     fn drop(&mut self) {
-        unsafe { ExReleasePushLockExclusive(&mut *self.lock) };
+        unsafe { ExReleasePushLockExclusive(self.lock.get()) };
 
         unsafe { KeLeaveCriticalRegion() };
     }
@@ -0,0 +1,734 @@
+//! This module provides the client side of I/O: requests that a driver builds itself and sends to
+//! the device of another driver, such as a disk, a volume or a peer driver.
+//!
+//! The synchronous calls on [`DeviceTarget`] build the request with
+//! `IoBuildDeviceIoControlRequest` or `IoBuildSynchronousFsdRequest`, which transfer the data
+//! straight from and to the buffers of the caller, and wait for it to complete. They can only be
+//! called at `PASSIVE_LEVEL`. An [`Irp`] is allocated with `IoAllocateIrp` and owns its buffers,
+//! so it can also be sent without waiting, with a callback that receives it back on completion.
+
+use core::mem::size_of;
+use core::ptr::{null_mut, NonNull};
+
+use wdk_sys::base::{
+    DEVICE_OBJECT, DO_BUFFERED_IO, DO_DIRECT_IO, FILE_OBJECT, IO_STATUS_BLOCK, IRP,
+    IRP_MJ_DEVICE_CONTROL, IRP_MJ_INTERNAL_DEVICE_CONTROL, IRP_MJ_READ, IRP_MJ_WRITE, KEVENT,
+    LARGE_INTEGER, NTSTATUS, PVOID, STATUS_MORE_PROCESSING_REQUIRED, STATUS_PENDING,
+    _EVENT_TYPE as EVENT_TYPE, _KWAIT_REASON as KWAIT_REASON, _MODE as MODE,
+    _POOL_TYPE as POOL_TYPE,
+};
+use wdk_sys::ntoskrnl::{
+    ExAllocatePoolWithTag, ExFreePoolWithTag, IoAllocateIrp, IoBuildDeviceIoControlRequest,
+    IoBuildSynchronousFsdRequest, IoCallDriver, IoFreeIrp, IoGetDeviceObjectPointer,
+    IoGetNextIrpStackLocation, IoSetCompletionRoutine, KeInitializeEvent, KeWaitForSingleObject,
+    ObDereferenceObject, ObReferenceObject,
+};
+
+use crate::allocator::Pool;
+use crate::device::{DeviceType, LowerDevice};
+use crate::error::{Error, IntoResult};
+use crate::ioctl::{ControlCode, Pod, TransferMethod};
+use crate::mdl::{AccessMode, LockOperation, Mdl};
+use crate::request::signal_completion;
+use crate::string::UnicodeString;
+
+const BUFFER_TAG: u32 = u32::from_ne_bytes(*b"rirp");
+const SEND_TAG: u32 = u32::from_ne_bytes(*b"rsnd");
+
+/// The final status of a request and the number of bytes it transferred, as reported by the
+/// driver that completed it.
+#[derive(Clone, Copy, Debug)]
+pub struct IoStatus {
+    pub status: NTSTATUS,
+    pub information: usize,
+}
+
+impl IoStatus {
+    /// Turns the status into an error unless it is a success or informational status.
+    pub fn into_result(self) -> Result<IoStatus, Error> {
+        if self.status >= 0 {
+            Ok(self)
+        } else {
+            Err(Error::from_ntstatus(self.status))
+        }
+    }
+}
+
+impl From<&IO_STATUS_BLOCK> for IoStatus {
+    fn from(io_status: &IO_STATUS_BLOCK) -> Self {
+        Self {
+            status: unsafe { io_status.__bindgen_anon_1.Status },
+            information: io_status.Information as usize,
+        }
+    }
+}
+
+/// A referenced device of another driver that requests can be sent to. The reference is released
+/// when the target is dropped.
+pub struct DeviceTarget {
+    device: *mut DEVICE_OBJECT,
+    file: *mut FILE_OBJECT,
+}
+
+unsafe impl Send for DeviceTarget {}
+unsafe impl Sync for DeviceTarget {}
+
+impl DeviceTarget {
+    /// Opens the named device, e.g. `\Device\Harddisk0\DR0`, with the given access mask, and
+    /// targets the top of its device stack. Requests are sent on the file object that was opened,
+    /// which is what most drivers expect.
+    pub fn open(name: &UnicodeString, access: u32) -> Result<Self, Error> {
+        let mut file: *mut FILE_OBJECT = null_mut();
+        let mut device = null_mut();
+
+        unsafe {
+            IoGetDeviceObjectPointer(
+                &mut name.to_unicode_string(),
+                access,
+                &mut file,
+                &mut device,
+            )
+        }
+        .into_result()?;
+
+        // The file object holds the reference to the device.
+        Ok(Self { device, file })
+    }
+
+    /// Targets the device below a filter or function device, e.g. to query it while handling a
+    /// request. Requests are sent without a file object.
+    pub fn from_lower(lower: &LowerDevice) -> Self {
+        let device = unsafe { lower.as_raw_mut() };
+
+        unsafe {
+            ObReferenceObject(device as _);
+        }
+
+        Self {
+            device,
+            file: null_mut(),
+        }
+    }
+
+    pub fn as_raw(&self) -> *mut DEVICE_OBJECT {
+        self.device
+    }
+
+    pub fn file_object(&self) -> *mut FILE_OBJECT {
+        self.file
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        unsafe { (*self.device).DeviceType.into() }
+    }
+
+    /// Sends a device control request with the given input and waits for its output. If the
+    /// driver returns less than a whole `Out`, this fails with `STATUS_BUFFER_TOO_SMALL`.
+    pub fn ioctl<In: Pod, Out: Pod>(
+        &self,
+        code: ControlCode,
+        input: &In,
+    ) -> Result<(Out, IoStatus), Error> {
+        self.typed_ioctl(code, input, false)
+    }
+
+    /// Sends an internal device control request with the given input and waits for its output,
+    /// see [`DeviceTarget::ioctl`].
+    pub fn internal_ioctl<In: Pod, Out: Pod>(
+        &self,
+        code: ControlCode,
+        input: &In,
+    ) -> Result<(Out, IoStatus), Error> {
+        self.typed_ioctl(code, input, true)
+    }
+
+    fn typed_ioctl<In: Pod, Out: Pod>(
+        &self,
+        code: ControlCode,
+        input: &In,
+        internal: bool,
+    ) -> Result<(Out, IoStatus), Error> {
+        // Any bit pattern is a valid `Out`.
+        let mut output: Out = unsafe { core::mem::zeroed() };
+
+        let status = unsafe {
+            self.raw_ioctl(
+                code,
+                input as *const In as _,
+                size_of::<In>(),
+                &mut output as *mut Out as _,
+                size_of::<Out>(),
+                internal,
+            )?
+        };
+
+        if status.information < size_of::<Out>() {
+            return Err(Error::BUFFER_TOO_SMALL);
+        }
+
+        Ok((output, status))
+    }
+
+    /// Sends a device control request with untyped buffers and waits for it to complete. The
+    /// number of bytes written to `output` is returned as the information of the status.
+    pub fn ioctl_bytes(
+        &self,
+        code: ControlCode,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<IoStatus, Error> {
+        unsafe {
+            self.raw_ioctl(
+                code,
+                input.as_ptr() as _,
+                input.len(),
+                output.as_mut_ptr() as _,
+                output.len(),
+                false,
+            )
+        }
+    }
+
+    unsafe fn raw_ioctl(
+        &self,
+        code: ControlCode,
+        input: PVOID,
+        input_size: usize,
+        output: PVOID,
+        output_size: usize,
+        internal: bool,
+    ) -> Result<IoStatus, Error> {
+        let mut event: KEVENT = core::mem::zeroed();
+        let mut io_status: IO_STATUS_BLOCK = core::mem::zeroed();
+
+        KeInitializeEvent(&mut event, EVENT_TYPE::NotificationEvent, 0);
+
+        let irp = IoBuildDeviceIoControlRequest(
+            code.raw(),
+            self.device,
+            if input_size == 0 { null_mut() } else { input },
+            buffer_size(input_size)?,
+            if output_size == 0 { null_mut() } else { output },
+            buffer_size(output_size)?,
+            internal as _,
+            &mut event,
+            &mut io_status,
+        );
+
+        if irp.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        self.call_and_wait(irp, &mut event, &io_status)
+    }
+
+    /// Reads from the device at `offset` into `buf`, and waits for the data. The number of bytes
+    /// read is returned as the information of the status.
+    pub fn read(&self, buf: &mut [u8], offset: i64) -> Result<IoStatus, Error> {
+        unsafe { self.fsd_request(IRP_MJ_READ, buf.as_mut_ptr() as _, buf.len(), offset) }
+    }
+
+    /// Writes `buf` to the device at `offset`, and waits for the write to complete.
+    pub fn write(&self, buf: &[u8], offset: i64) -> Result<IoStatus, Error> {
+        unsafe { self.fsd_request(IRP_MJ_WRITE, buf.as_ptr() as _, buf.len(), offset) }
+    }
+
+    unsafe fn fsd_request(
+        &self,
+        major: u32,
+        buf: PVOID,
+        size: usize,
+        offset: i64,
+    ) -> Result<IoStatus, Error> {
+        let mut event: KEVENT = core::mem::zeroed();
+        let mut io_status: IO_STATUS_BLOCK = core::mem::zeroed();
+        let mut byte_offset: LARGE_INTEGER = core::mem::zeroed();
+
+        byte_offset.QuadPart = offset;
+
+        KeInitializeEvent(&mut event, EVENT_TYPE::NotificationEvent, 0);
+
+        let irp = IoBuildSynchronousFsdRequest(
+            major,
+            self.device,
+            buf,
+            buffer_size(size)?,
+            &mut byte_offset,
+            &mut event,
+            &mut io_status,
+        );
+
+        if irp.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        self.call_and_wait(irp, &mut event, &io_status)
+    }
+
+    /// Sends a request that was built by the I/O manager with an event and a status block, which
+    /// the I/O manager fills in and signals once the request has completed.
+    unsafe fn call_and_wait(
+        &self,
+        irp: *mut IRP,
+        event: &mut KEVENT,
+        io_status: &IO_STATUS_BLOCK,
+    ) -> Result<IoStatus, Error> {
+        (*IoGetNextIrpStackLocation(irp)).FileObject = self.file;
+
+        let status = IoCallDriver(self.device, irp);
+
+        if status == STATUS_PENDING {
+            KeWaitForSingleObject(
+                event as *mut KEVENT as _,
+                KWAIT_REASON::Executive,
+                MODE::KernelMode as _,
+                0,
+                null_mut(),
+            );
+
+            IoStatus::from(io_status).into_result()
+        } else {
+            IoStatus {
+                status,
+                information: io_status.Information as usize,
+            }
+            .into_result()
+        }
+    }
+
+    /// Returns the object that holds the reference to the device.
+    fn referenced(&self) -> PVOID {
+        if self.file.is_null() {
+            self.device as _
+        } else {
+            self.file as _
+        }
+    }
+}
+
+impl Clone for DeviceTarget {
+    fn clone(&self) -> Self {
+        unsafe {
+            ObReferenceObject(self.referenced());
+        }
+
+        Self {
+            device: self.device,
+            file: self.file,
+        }
+    }
+}
+
+impl Drop for DeviceTarget {
+    fn drop(&mut self) {
+        unsafe {
+            ObDereferenceObject(self.referenced());
+        }
+    }
+}
+
+/// A request that was allocated by the driver itself, along with the non-paged buffers it
+/// transfers data from and to. It can be sent once, after which it is handed back as a
+/// [`CompletedIrp`] with its final status.
+pub struct Irp {
+    raw: NonNull<IRP>,
+    target: DeviceTarget,
+    // The MDL locks the pages of `buffer`, so it has to be dropped first.
+    mdl: Option<Mdl>,
+    input: Option<IrpBuffer>,
+    buffer: Option<IrpBuffer>,
+    output_size: usize,
+}
+
+unsafe impl Send for Irp {}
+
+impl Irp {
+    fn allocate(target: &DeviceTarget) -> Result<Self, Error> {
+        let stack_size = unsafe { (*target.device).StackSize };
+        let raw = unsafe { IoAllocateIrp(stack_size, 0) };
+
+        let raw = NonNull::new(raw).ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        unsafe {
+            (*IoGetNextIrpStackLocation(raw.as_ptr())).FileObject = target.file;
+        }
+
+        Ok(Self {
+            raw,
+            target: target.clone(),
+            mdl: None,
+            input: None,
+            buffer: None,
+            output_size: 0,
+        })
+    }
+
+    /// Builds a device control request for `target`, which copies `input` and has room for
+    /// `output_size` bytes of output. The buffers are passed to the target the way the transfer
+    /// method of `code` prescribes.
+    pub fn device_control(
+        target: &DeviceTarget,
+        code: ControlCode,
+        input: &[u8],
+        output_size: usize,
+    ) -> Result<Self, Error> {
+        Self::build_device_control(target, code, input, output_size, IRP_MJ_DEVICE_CONTROL)
+    }
+
+    /// Builds an internal device control request, see [`Irp::device_control`].
+    pub fn internal_device_control(
+        target: &DeviceTarget,
+        code: ControlCode,
+        input: &[u8],
+        output_size: usize,
+    ) -> Result<Self, Error> {
+        Self::build_device_control(
+            target,
+            code,
+            input,
+            output_size,
+            IRP_MJ_INTERNAL_DEVICE_CONTROL,
+        )
+    }
+
+    fn build_device_control(
+        target: &DeviceTarget,
+        code: ControlCode,
+        input: &[u8],
+        output_size: usize,
+        major: u32,
+    ) -> Result<Self, Error> {
+        let input_length = buffer_size(input.len())?;
+        let output_length = buffer_size(output_size)?;
+
+        let mut irp = Self::allocate(target)?;
+        let raw = irp.raw.as_ptr();
+
+        irp.output_size = output_size;
+
+        match code.transfer_method() {
+            TransferMethod::Buffered => {
+                // The input and output share the system buffer.
+                let mut buffer = IrpBuffer::new(input.len().max(output_size))?;
+                buffer.as_mut_slice()[..input.len()].copy_from_slice(input);
+
+                unsafe { (*raw).AssociatedIrp.SystemBuffer = buffer.as_ptr() };
+                irp.buffer = Some(buffer);
+            }
+            TransferMethod::InputDirect | TransferMethod::OutputDirect => {
+                let operation = if code.transfer_method() == TransferMethod::InputDirect {
+                    LockOperation::Read
+                } else {
+                    LockOperation::Write
+                };
+
+                if !input.is_empty() {
+                    let mut buffer = IrpBuffer::new(input.len())?;
+                    buffer.as_mut_slice().copy_from_slice(input);
+
+                    unsafe { (*raw).AssociatedIrp.SystemBuffer = buffer.as_ptr() };
+                    irp.input = Some(buffer);
+                }
+
+                irp.set_locked_output(output_size, operation)?;
+            }
+            TransferMethod::Neither => {
+                if !input.is_empty() {
+                    let mut buffer = IrpBuffer::new(input.len())?;
+                    buffer.as_mut_slice().copy_from_slice(input);
+                    irp.input = Some(buffer);
+                }
+
+                let buffer = IrpBuffer::new(output_size)?;
+
+                unsafe { (*raw).UserBuffer = buffer.as_ptr() };
+                irp.buffer = Some(buffer);
+            }
+        }
+
+        unsafe {
+            let stack_location = &mut *IoGetNextIrpStackLocation(raw);
+
+            stack_location.MajorFunction = major as _;
+            stack_location.Parameters.DeviceIoControl.InputBufferLength = input_length;
+            stack_location.Parameters.DeviceIoControl.OutputBufferLength = output_length;
+            stack_location.Parameters.DeviceIoControl.IoControlCode = code.raw();
+
+            if code.transfer_method() == TransferMethod::Neither {
+                stack_location.Parameters.DeviceIoControl.Type3InputBuffer = irp
+                    .input
+                    .as_ref()
+                    .map_or(null_mut(), |input| input.as_ptr());
+            }
+        }
+
+        Ok(irp)
+    }
+
+    /// Builds a request that reads `length` bytes at `offset` from `target`.
+    pub fn read(target: &DeviceTarget, length: usize, offset: i64) -> Result<Self, Error> {
+        let mut irp = Self::build_transfer(target, length, offset, IRP_MJ_READ)?;
+
+        irp.output_size = length;
+
+        Ok(irp)
+    }
+
+    /// Builds a request that writes a copy of `data` at `offset` to `target`.
+    pub fn write(target: &DeviceTarget, data: &[u8], offset: i64) -> Result<Self, Error> {
+        let mut irp = Self::build_transfer(target, data.len(), offset, IRP_MJ_WRITE)?;
+
+        if let Some(buffer) = irp.buffer.as_mut() {
+            buffer.as_mut_slice().copy_from_slice(data);
+        }
+
+        Ok(irp)
+    }
+
+    fn build_transfer(
+        target: &DeviceTarget,
+        length: usize,
+        offset: i64,
+        major: u32,
+    ) -> Result<Self, Error> {
+        let size = buffer_size(length)?;
+        let flags = unsafe { (*target.device).Flags };
+
+        let mut irp = Self::allocate(target)?;
+        let raw = irp.raw.as_ptr();
+
+        if flags & DO_DIRECT_IO != 0 {
+            let operation = if major == IRP_MJ_READ {
+                LockOperation::Write
+            } else {
+                LockOperation::Read
+            };
+
+            irp.set_locked_output(length, operation)?;
+        } else {
+            let buffer = IrpBuffer::new(length)?;
+
+            unsafe {
+                if flags & DO_BUFFERED_IO != 0 {
+                    (*raw).AssociatedIrp.SystemBuffer = buffer.as_ptr();
+                } else {
+                    (*raw).UserBuffer = buffer.as_ptr();
+                }
+            }
+
+            irp.buffer = Some(buffer);
+        }
+
+        unsafe {
+            let stack_location = &mut *IoGetNextIrpStackLocation(raw);
+
+            stack_location.MajorFunction = major as _;
+
+            // Read and write share the layout of their parameters.
+            stack_location.Parameters.Read.Length = size;
+            stack_location.Parameters.Read.ByteOffset.QuadPart = offset;
+        }
+
+        Ok(irp)
+    }
+
+    /// Allocates the data buffer and describes it with a locked MDL, for direct I/O.
+    fn set_locked_output(&mut self, size: usize, operation: LockOperation) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let buffer = IrpBuffer::new(size)?;
+        let mut mdl = unsafe { Mdl::new(buffer.as_ptr(), size)? };
+
+        mdl.probe_and_lock(AccessMode::KernelMode, operation)?;
+
+        unsafe { (*self.raw.as_ptr()).MdlAddress = mdl.as_raw() };
+
+        self.buffer = Some(buffer);
+        self.mdl = Some(mdl);
+
+        Ok(())
+    }
+
+    pub fn as_raw(&self) -> *mut IRP {
+        self.raw.as_ptr()
+    }
+
+    /// Sends the request and waits for it to complete. This can only be called at
+    /// `PASSIVE_LEVEL`.
+    pub fn send_and_wait(self) -> CompletedIrp {
+        let mut event: KEVENT = unsafe { core::mem::zeroed() };
+        let raw = self.raw.as_ptr();
+
+        unsafe {
+            KeInitializeEvent(&mut event, EVENT_TYPE::NotificationEvent, 0);
+
+            IoSetCompletionRoutine(
+                raw,
+                Some(signal_completion),
+                &mut event as *mut KEVENT as PVOID,
+                1,
+                1,
+                1,
+            );
+
+            if IoCallDriver(self.target.device, raw) == STATUS_PENDING {
+                KeWaitForSingleObject(
+                    &mut event as *mut KEVENT as _,
+                    KWAIT_REASON::Executive,
+                    MODE::KernelMode as _,
+                    0,
+                    null_mut(),
+                );
+            }
+        }
+
+        CompletedIrp(self)
+    }
+
+    /// Sends the request without waiting for it. `callback` receives the request back once it
+    /// has completed, which may happen at `DISPATCH_LEVEL` and before this returns.
+    ///
+    /// Returns the status that the target returned for the request, e.g. `STATUS_PENDING`. The
+    /// callback is called regardless of it, unless the request could not be sent at all.
+    pub fn send<F>(self, callback: F) -> Result<NTSTATUS, Error>
+    where
+        F: FnOnce(CompletedIrp) + Send + 'static,
+    {
+        let raw = self.raw.as_ptr();
+        let device = self.target.device;
+
+        let context = Pool::new((self, callback), POOL_TYPE::NonPagedPoolNx, SEND_TAG)
+            .ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        let status = unsafe {
+            IoSetCompletionRoutine(
+                raw,
+                Some(send_completion::<F>),
+                context.into_raw() as PVOID,
+                1,
+                1,
+                1,
+            );
+
+            IoCallDriver(device, raw)
+        };
+
+        Ok(status)
+    }
+}
+
+/// A request that was sent and has completed. It cannot be sent again, and is freed along with its
+/// buffers when it is dropped.
+pub struct CompletedIrp(Irp);
+
+impl CompletedIrp {
+    pub fn as_raw(&self) -> *mut IRP {
+        self.0.as_raw()
+    }
+
+    /// Returns the final status of the request.
+    pub fn io_status(&self) -> IoStatus {
+        IoStatus::from(unsafe { &(*self.0.raw.as_ptr()).IoStatus })
+    }
+
+    /// Returns the output that the target transferred, i.e. the first bytes of the output buffer
+    /// as reported by the information of the final status.
+    pub fn output(&self) -> &[u8] {
+        let size = self.io_status().information.min(self.0.output_size);
+
+        match &self.0.buffer {
+            Some(buffer) => &buffer.as_slice()[..size],
+            None => &[],
+        }
+    }
+
+    /// Reads the output as a `T`, which fails with `STATUS_BUFFER_TOO_SMALL` if the target did
+    /// not transfer enough data.
+    pub fn output_as<T: Pod>(&self) -> Result<T, Error> {
+        let output = self.output();
+
+        if output.len() < size_of::<T>() {
+            return Err(Error::BUFFER_TOO_SMALL);
+        }
+
+        Ok(unsafe { (output.as_ptr() as *const T).read_unaligned() })
+    }
+}
+
+unsafe extern "C" fn send_completion<F>(
+    _device: *mut DEVICE_OBJECT,
+    _irp: *mut IRP,
+    context: PVOID,
+) -> NTSTATUS
+where
+    F: FnOnce(CompletedIrp),
+{
+    let (irp, callback) = Pool::from_raw(context as *mut (Irp, F), SEND_TAG).into_inner();
+
+    callback(CompletedIrp(irp));
+
+    // The request belongs to the driver, which frees it when it drops the `Irp`.
+    STATUS_MORE_PROCESSING_REQUIRED
+}
+
+impl Drop for Irp {
+    fn drop(&mut self) {
+        let raw = self.raw.as_ptr();
+
+        unsafe {
+            // The MDL is owned by `self.mdl`, which unlocks and frees it.
+            (*raw).MdlAddress = null_mut();
+
+            IoFreeIrp(raw);
+        }
+    }
+}
+
+/// A non-paged buffer that a request transfers data from or to, which has to stay valid until the
+/// request has completed, possibly at `DISPATCH_LEVEL`.
+struct IrpBuffer {
+    ptr: NonNull<u8>,
+    size: usize,
+}
+
+impl IrpBuffer {
+    fn new(size: usize) -> Result<Self, Error> {
+        let ptr = unsafe {
+            ExAllocatePoolWithTag(POOL_TYPE::NonPagedPoolNx, size.max(1) as _, BUFFER_TAG)
+        };
+
+        let ptr = NonNull::new(ptr as *mut u8).ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        unsafe {
+            core::ptr::write_bytes(ptr.as_ptr(), 0, size);
+        }
+
+        Ok(Self { ptr, size })
+    }
+
+    fn as_ptr(&self) -> PVOID {
+        self.ptr.as_ptr() as _
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.size) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.size) }
+    }
+}
+
+impl Drop for IrpBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            ExFreePoolWithTag(self.ptr.as_ptr() as _, BUFFER_TAG);
+        }
+    }
+}
+
+fn buffer_size(size: usize) -> Result<u32, Error> {
+    u32::try_from(size).map_err(|_| Error::INVALID_PARAMETER)
+}
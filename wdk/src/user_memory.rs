@@ -0,0 +1,84 @@
+//! This module provides guarded access to memory that cannot be trusted, such as buffers that were
+//! passed by user mode with `METHOD_NEITHER`. The memory behind such a pointer can be freed or
+//! protected by another thread at any time, so it is only ever probed and copied inside a
+//! structured exception handler, which turns a fault into an [`Error`] instead of a bugcheck.
+//!
+//! Rust has no `__try`/`__except`, so the probes and copies are done by functions in the C wrapper
+//! of `wdk-sys`, which never run any Rust code inside the handler.
+
+use core::mem::size_of;
+
+use wdk_sys::ntoskrnl::{CopyMemoryGuarded, ProbeForReadGuarded, ProbeForWriteGuarded};
+
+use wdk_ioctl::Pod;
+
+use crate::error::{Error, IntoResult};
+
+/// Checks that `size` bytes at `ptr` are a readable user-mode range that is aligned to
+/// `alignment`. An empty range is always valid.
+pub fn probe_for_read(ptr: *const cty::c_void, size: usize, alignment: u32) -> Result<(), Error> {
+    unsafe { ProbeForReadGuarded(ptr, size as _, alignment) }.into_result()
+}
+
+/// Checks that `size` bytes at `ptr` are a writable user-mode range that is aligned to
+/// `alignment`. An empty range is always valid.
+pub fn probe_for_write(ptr: *mut cty::c_void, size: usize, alignment: u32) -> Result<(), Error> {
+    unsafe { ProbeForWriteGuarded(ptr, size as _, alignment) }.into_result()
+}
+
+/// Copies `size` bytes from `src` to `dst`, returning the exception code as an error if either
+/// range faults, e.g. [`Error::ACCESS_VIOLATION`].
+///
+/// # Safety
+///
+/// Faults are only caught for addresses that raise an exception, i.e. user-mode addresses and
+/// pageable memory. The ranges must have been probed if they come from user mode, otherwise a
+/// caller can make the kernel read or write kernel memory on its behalf.
+pub unsafe fn copy_guarded(
+    dst: *mut cty::c_void,
+    src: *const cty::c_void,
+    size: usize,
+) -> Result<(), Error> {
+    if size == 0 {
+        return Ok(());
+    }
+
+    CopyMemoryGuarded(dst, src, size as _).into_result()
+}
+
+/// Copies `dst.len()` bytes from the user-mode address `src` of the current process.
+pub fn copy_from_user(dst: &mut [u8], src: *const cty::c_void) -> Result<(), Error> {
+    probe_for_read(src, dst.len(), 1)?;
+
+    unsafe { copy_guarded(dst.as_mut_ptr() as _, src, dst.len()) }
+}
+
+/// Copies `src` to the user-mode address `dst` of the current process.
+pub fn copy_to_user(dst: *mut cty::c_void, src: &[u8]) -> Result<(), Error> {
+    probe_for_write(dst, src.len(), 1)?;
+
+    unsafe { copy_guarded(dst, src.as_ptr() as _, src.len()) }
+}
+
+/// Reads a `T` from the user-mode address `src` of the current process, which does not need to be
+/// aligned for `T`.
+pub fn read_user<T: Pod>(src: *const T) -> Result<T, Error> {
+    // Any bit pattern is a valid `T`.
+    let mut value: T = unsafe { core::mem::zeroed() };
+
+    probe_for_read(src as _, size_of::<T>(), 1)?;
+
+    unsafe {
+        copy_guarded(&mut value as *mut T as _, src as _, size_of::<T>())?;
+    }
+
+    Ok(value)
+}
+
+/// Writes `value` to the user-mode address `dst` of the current process, which does not need to
+/// be aligned for `T`.
+pub fn write_user<T: Pod>(dst: *mut T, value: &T) -> Result<(), Error> {
+    probe_for_write(dst as _, size_of::<T>(), 1)?;
+
+    unsafe { copy_guarded(dst as _, value as *const T as _, size_of::<T>()) }
+}
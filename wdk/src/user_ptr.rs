@@ -1,154 +1,520 @@
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use wdk_ioctl::Pod;
+
 use crate::error::Error;
+use crate::user_memory::{copy_guarded, probe_for_read, probe_for_write};
 
-pub enum UserPtr {
-    Buffered {
-        ptr: *mut cty::c_void,
-        read_size: usize,
-        write_size: usize,
-    },
-    Direct {
-        read_ptr: *const cty::c_void,
-        write_ptr: *mut cty::c_void,
-        read_size: usize,
-        write_size: usize,
-    },
-    Neither,
+/// The buffers of a request. A `UserPtr` borrows its request mutably, so a request only lends out
+/// one at a time, and the views of its input and output buffers cannot alias each other.
+pub struct UserPtr<'a> {
+    input: Buffer,
+    output: Buffer,
+    _request: PhantomData<&'a mut ()>,
+}
+
+/// One of the buffers of a request.
+#[derive(Copy, Clone)]
+pub(crate) struct Buffer {
+    ptr: *mut cty::c_void,
+    size: usize,
+    kind: BufferKind,
+}
+
+#[derive(Copy, Clone)]
+enum BufferKind {
+    /// A buffer in system memory that the I/O manager allocated for the request, e.g. the system
+    /// buffer of a buffered request. Only the driver that owns the request accesses it.
+    System,
+    /// User pages that are mapped through the MDL of a direct I/O request. The caller can still
+    /// change them at any time, so they are only borrowed by the unsafe views.
+    Mapped,
+    /// A buffer of a `METHOD_NEITHER` request, which is passed as it is by the caller. It is never
+    /// borrowed, but only copied from and to inside an exception handler, and is probed first if
+    /// the request came from user mode.
+    Neither { probe: bool },
 }
 
-impl UserPtr {
-    pub unsafe fn new_buffered(ptr: *mut cty::c_void, read_size: usize, write_size: usize) -> Self {
-        Self::Buffered {
+impl Buffer {
+    pub(crate) fn system(ptr: *mut cty::c_void, size: usize) -> Self {
+        Self {
             ptr,
-            read_size,
-            write_size,
+            size,
+            kind: BufferKind::System,
         }
     }
 
-    pub unsafe fn new_direct(
-        read_ptr: *const cty::c_void,
-        write_ptr: *mut cty::c_void,
+    pub(crate) fn mapped(ptr: *mut cty::c_void, size: usize) -> Self {
+        Self {
+            ptr,
+            size,
+            kind: BufferKind::Mapped,
+        }
+    }
+
+    pub(crate) fn neither(ptr: *mut cty::c_void, size: usize, probe: bool) -> Self {
+        Self {
+            ptr,
+            size,
+            kind: BufferKind::Neither { probe },
+        }
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self::system(core::ptr::null_mut(), 0)
+    }
+}
+
+impl<'a> UserPtr<'a> {
+    /// Creates the buffers of a request. The buffers have to stay valid for `'a`, and must not be
+    /// accessed through anything else than the returned `UserPtr` in the meantime.
+    pub(crate) unsafe fn new(input: Buffer, output: Buffer) -> Self {
+        Self {
+            input,
+            output,
+            _request: PhantomData,
+        }
+    }
+
+    /// Creates the buffers of a buffered request, whose input and output share the same buffer.
+    pub(crate) unsafe fn new_buffered(
+        ptr: *mut cty::c_void,
         read_size: usize,
         write_size: usize,
     ) -> Self {
-        Self::Direct {
-            read_ptr,
-            write_ptr,
-            read_size,
-            write_size,
+        Self::new(
+            Buffer::system(ptr, read_size),
+            Buffer::system(ptr, write_size),
+        )
+    }
+
+    pub fn read_size(&self) -> usize {
+        self.input().1
+    }
+
+    pub fn write_size(&self) -> usize {
+        self.output().1
+    }
+
+    fn input(&self) -> (*const cty::c_void, usize) {
+        (self.input.ptr as _, self.input.size)
+    }
+
+    fn output(&self) -> (*mut cty::c_void, usize) {
+        (self.output.ptr, self.output.size)
+    }
+
+    /// Borrows the start of the input buffer as a `T`.
+    ///
+    /// This fails with `STATUS_DATATYPE_MISALIGNMENT` if the buffer is not aligned for `T`, with
+    /// `STATUS_INVALID_USER_BUFFER` if it is too small and with `STATUS_INVALID_PARAMETER` if it
+    /// is not in system memory. The user pages of direct I/O requests can still be changed by the
+    /// caller, and the buffers of `METHOD_NEITHER` requests are passed as they are, so these can
+    /// only be copied from with [`UserPtr::read`] or a [`Reader`], or borrowed with the unsafe
+    /// [`UserPtr::as_mapped_slice`].
+    pub fn as_ref<T: Pod>(&self) -> Result<&T, Error> {
+        let ptr = self.input_view::<T>(size_of::<T>())?;
+
+        Ok(unsafe { &*(ptr as *const T) })
+    }
+
+    /// Borrows the start of the output buffer as a `T`, see [`UserPtr::as_ref`].
+    pub fn as_mut<T: Pod>(&mut self) -> Result<&mut T, Error> {
+        let ptr = self.output_view::<T>(size_of::<T>())?;
+
+        Ok(unsafe { &mut *(ptr as *mut T) })
+    }
+
+    /// Borrows the input buffer as a slice of as many `T`s as fit into it, see
+    /// [`UserPtr::as_ref`].
+    pub fn as_slice<T: Pod>(&self) -> Result<&[T], Error> {
+        let ptr = self.input_view::<T>(0)?;
+        let len = slice_len::<T>(self.read_size());
+
+        if len == 0 {
+            return Ok(&[]);
         }
+
+        Ok(unsafe { core::slice::from_raw_parts(ptr as *const T, len) })
     }
 
-    pub unsafe fn new_neither() -> Self {
-        Self::Neither
+    /// Borrows the output buffer as a slice of as many `T`s as fit into it, see
+    /// [`UserPtr::as_ref`].
+    pub fn as_mut_slice<T: Pod>(&mut self) -> Result<&mut [T], Error> {
+        let ptr = self.output_view::<T>(0)?;
+        let len = slice_len::<T>(self.write_size());
+
+        if len == 0 {
+            return Ok(&mut []);
+        }
+
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut T, len) })
     }
 
-    pub fn read_size(&self) -> usize {
-        match self {
-            Self::Buffered { read_size, .. } => *read_size,
-            Self::Direct { read_size, .. } => *read_size,
-            Self::Neither => 0,
+    /// Borrows the input buffer as a variable-length struct, i.e. a header `H` that is followed
+    /// by as many `E`s as fit into the rest of the buffer. The elements start at the first offset
+    /// after the header that is aligned for `E`, like they would in a `#[repr(C)]` struct.
+    pub fn as_ref_with_tail<H: Pod, E: Pod>(&self) -> Result<(&H, &[E]), Error> {
+        let header = self.as_ref::<H>()?;
+
+        let offset = round_up(size_of::<H>(), align_of::<E>());
+        let len = slice_len::<E>(self.read_size().saturating_sub(offset));
+
+        if len == 0 {
+            return Ok((header, &[]));
+        }
+
+        let ptr = unsafe { (header as *const H as *const u8).add(offset) };
+
+        if ptr as usize % align_of::<E>() != 0 {
+            return Err(Error::DATATYPE_MISALIGNMENT);
+        }
+
+        Ok((header, unsafe {
+            core::slice::from_raw_parts(ptr as *const E, len)
+        }))
+    }
+
+    /// Returns a cursor that reads the input buffer from the start.
+    pub fn reader(&self) -> Reader<'_, 'a> {
+        Reader {
+            user_ptr: self,
+            offset: 0,
         }
     }
 
-    pub fn write_size(&self) -> usize {
-        match self {
-            Self::Buffered { write_size, .. } => *write_size,
-            Self::Direct { write_size, .. } => *write_size,
-            Self::Neither => 0,
+    /// Returns a cursor that writes the output buffer from the start, and keeps track of how many
+    /// bytes were written.
+    pub fn writer(&mut self) -> Writer<'_, 'a> {
+        Writer {
+            user_ptr: self,
+            offset: 0,
         }
     }
 
-    pub fn as_slice(&self) -> &[u8] {
-        let (ptr, size) = match self {
-            Self::Buffered { ptr, read_size, .. } => (*ptr as _, *read_size),
-            Self::Direct {
-                read_ptr,
-                read_size,
-                ..
-            } => (*read_ptr, *read_size),
-            Self::Neither => (core::ptr::null(), 0),
-        };
+    /// Borrows the input buffer as a slice of as many `T`s as fit into it, like
+    /// [`UserPtr::as_slice`], but also if the buffer consists of user pages that are mapped for a
+    /// direct I/O request.
+    ///
+    /// # Safety
+    ///
+    /// The caller of the request can change mapped user pages at any time, and may pass the same
+    /// pages as the input and the output buffer. The caller of this function has to make sure
+    /// that the pages are neither changed nor written through the output buffer while they are
+    /// borrowed.
+    pub unsafe fn as_mapped_slice<T: Pod>(&self) -> Result<&[T], Error> {
+        let ptr = view::<T>(&self.input, 0, true)?;
+        let len = slice_len::<T>(self.read_size());
 
-        if ptr.is_null() || size == 0 {
-            &[]
-        } else {
-            unsafe { core::slice::from_raw_parts(ptr as *const u8, size) }
+        if len == 0 {
+            return Ok(&[]);
         }
+
+        Ok(core::slice::from_raw_parts(ptr as *const T, len))
     }
 
-    pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        let (ptr, size) = match self {
-            Self::Buffered {
-                ptr, write_size, ..
-            } => (*ptr, *write_size),
-            Self::Direct {
-                write_ptr,
-                write_size,
-                ..
-            } => (*write_ptr, *write_size),
-            Self::Neither => (core::ptr::null_mut(), 0),
-        };
+    /// Borrows the output buffer as a slice of as many `T`s as fit into it, like
+    /// [`UserPtr::as_mut_slice`], but also if the buffer consists of user pages that are mapped
+    /// for a direct I/O request.
+    ///
+    /// # Safety
+    ///
+    /// See [`UserPtr::as_mapped_slice`].
+    pub unsafe fn as_mapped_mut_slice<T: Pod>(&mut self) -> Result<&mut [T], Error> {
+        let ptr = view::<T>(&self.output, 0, true)?;
+        let len = slice_len::<T>(self.write_size());
 
-        if ptr.is_null() || size == 0 {
-            &mut []
-        } else {
-            unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, size) }
+        if len == 0 {
+            return Ok(&mut []);
         }
+
+        Ok(core::slice::from_raw_parts_mut(ptr, len))
+    }
+
+    /// Checks that the input buffer can be borrowed as `size` bytes that are aligned for `T`.
+    fn input_view<T>(&self, size: usize) -> Result<*const cty::c_void, Error> {
+        view::<T>(&self.input, size, false).map(|ptr| ptr as _)
+    }
+
+    /// Checks that the output buffer can be borrowed as `size` bytes that are aligned for `T`.
+    fn output_view<T>(&self, size: usize) -> Result<*mut cty::c_void, Error> {
+        view::<T>(&self.output, size, false).map(|ptr| ptr as _)
+    }
+
+    pub fn read<T: Pod>(&self) -> Result<T, Error> {
+        self.read_at(0)
+    }
+
+    pub fn write<T: Pod>(&mut self, obj: &T) -> Result<(), Error> {
+        self.write_at(0, obj)
     }
 
-    pub fn read<T: Copy + Default>(&self) -> Result<T, Error> {
-        let (ptr, size) = match self {
-            Self::Buffered { ptr, read_size, .. } => (*ptr as _, *read_size),
-            Self::Direct {
-                read_ptr,
-                read_size,
-                ..
-            } => (*read_ptr, *read_size),
-            Self::Neither => (core::ptr::null(), 0),
-        };
+    fn read_at<T: Pod>(&self, offset: usize) -> Result<T, Error> {
+        // Any bit pattern is a valid `T`.
+        let mut obj: T = unsafe { core::mem::zeroed() };
+
+        if size_of::<T>() == 0 {
+            return Ok(obj);
+        }
+
+        let (ptr, size) = self.input();
 
         if ptr.is_null() || size == 0 {
             return Err(Error::INVALID_PARAMETER);
         }
 
-        if core::mem::size_of::<T>() > size {
+        if size_of::<T>() > size.saturating_sub(offset) {
             return Err(Error::INVALID_USER_BUFFER);
         }
 
-        let mut obj = T::default();
-
         unsafe {
-            core::ptr::copy_nonoverlapping(ptr as _, &mut obj, 1);
+            self.copy_in(offset, &mut obj as *mut T as _, size_of::<T>())?;
         }
 
         Ok(obj)
     }
 
-    pub fn write<T: Copy>(&mut self, obj: &T) -> Result<(), Error> {
-        let (ptr, size) = match self {
-            Self::Buffered {
-                ptr, write_size, ..
-            } => (*ptr, *write_size),
-            Self::Direct {
-                write_ptr,
-                write_size,
-                ..
-            } => (*write_ptr, *write_size),
-            Self::Neither => (core::ptr::null_mut(), 0),
-        };
+    fn write_at<T: Pod>(&self, offset: usize, obj: &T) -> Result<(), Error> {
+        if size_of::<T>() == 0 {
+            return Ok(());
+        }
+
+        let (ptr, size) = self.output();
 
         if ptr.is_null() || size == 0 {
             return Err(Error::INVALID_PARAMETER);
         }
 
-        if core::mem::size_of::<T>() > size {
+        if size_of::<T>() > size.saturating_sub(offset) {
             return Err(Error::INVALID_USER_BUFFER);
         }
 
-        unsafe {
-            core::ptr::copy_nonoverlapping(obj, ptr as _, 1);
+        unsafe { self.copy_out(offset, obj as *const T as _, size_of::<T>()) }
+    }
+
+    /// Copies the start of the input buffer into `buf`, and returns the number of bytes copied.
+    pub fn read_bytes(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.read_bytes_at(0, buf)
+    }
+
+    /// Copies `data` to the start of the output buffer, and returns the number of bytes copied.
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.write_bytes_at(0, data)
+    }
+
+    fn read_bytes_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let (ptr, size) = self.input();
+        let size = size.saturating_sub(offset).min(buf.len());
+
+        if ptr.is_null() && size != 0 {
+            return Err(Error::INVALID_PARAMETER);
         }
 
+        unsafe { self.copy_in(offset, buf.as_mut_ptr() as _, size)? };
+
+        Ok(size)
+    }
+
+    fn write_bytes_at(&self, offset: usize, data: &[u8]) -> Result<usize, Error> {
+        let (ptr, size) = self.output();
+        let size = size.saturating_sub(offset).min(data.len());
+
+        if ptr.is_null() && size != 0 {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        unsafe { self.copy_out(offset, data.as_ptr() as _, size)? };
+
+        Ok(size)
+    }
+
+    /// Copies `size` bytes from `offset` into the input buffer, which has to be large enough.
+    unsafe fn copy_in(
+        &self,
+        offset: usize,
+        dst: *mut cty::c_void,
+        size: usize,
+    ) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let ptr = (self.input().0 as *const u8).add(offset) as *const cty::c_void;
+
+        match self.input.kind {
+            BufferKind::Neither { probe } => {
+                if probe {
+                    probe_for_read(ptr, size, 1)?;
+                }
+
+                copy_guarded(dst, ptr, size)
+            }
+            _ => {
+                core::ptr::copy_nonoverlapping(ptr as *const u8, dst as *mut u8, size);
+                Ok(())
+            }
+        }
+    }
+
+    /// Copies `size` bytes to `offset` into the output buffer, which has to be large enough.
+    unsafe fn copy_out(
+        &self,
+        offset: usize,
+        src: *const cty::c_void,
+        size: usize,
+    ) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+
+        let ptr = (self.output().0 as *mut u8).add(offset) as *mut cty::c_void;
+
+        match self.output.kind {
+            BufferKind::Neither { probe } => {
+                if probe {
+                    probe_for_write(ptr, size, 1)?;
+                }
+
+                copy_guarded(ptr, src, size)
+            }
+            _ => {
+                core::ptr::copy_nonoverlapping(src as *const u8, ptr as *mut u8, size);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A cursor over the input buffer of a request, which reads one value after the other. Values do
+/// not need to be aligned, as they are copied out of the buffer.
+pub struct Reader<'a, 'r> {
+    user_ptr: &'a UserPtr<'r>,
+    offset: usize,
+}
+
+impl<'a, 'r> Reader<'a, 'r> {
+    /// Reads the next `T`, or fails with `STATUS_INVALID_USER_BUFFER` if the rest of the buffer
+    /// is too small, in which case nothing is consumed.
+    pub fn read<T: Pod>(&mut self) -> Result<T, Error> {
+        let obj = self.user_ptr.read_at(self.offset)?;
+
+        self.offset += size_of::<T>();
+
+        Ok(obj)
+    }
+
+    /// Copies as many of the next bytes as fit into `buf`, and returns the number of bytes copied.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let size = self.user_ptr.read_bytes_at(self.offset, buf)?;
+
+        self.offset += size;
+
+        Ok(size)
+    }
+
+    /// Returns the number of bytes that have been read so far.
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of bytes that are left to be read.
+    pub fn remaining(&self) -> usize {
+        self.user_ptr.read_size().saturating_sub(self.offset)
+    }
+}
+
+/// A cursor over the output buffer of a request, which writes one value after the other. The
+/// number of bytes that were written is what a request should report as `Information` when it is
+/// completed.
+///
+/// For `METHOD_BUFFERED` requests the input and output buffers are the same, so everything has to
+/// be read from the input before the output is written.
+pub struct Writer<'a, 'r> {
+    user_ptr: &'a mut UserPtr<'r>,
+    offset: usize,
+}
+
+impl<'a, 'r> Writer<'a, 'r> {
+    /// Writes `obj` after the previous values, or fails with `STATUS_INVALID_USER_BUFFER` if the
+    /// rest of the buffer is too small, in which case nothing is written.
+    pub fn write<T: Pod>(&mut self, obj: &T) -> Result<(), Error> {
+        self.user_ptr.write_at(self.offset, obj)?;
+
+        self.offset += size_of::<T>();
+
         Ok(())
     }
+
+    /// Copies as much of `data` as fits after the previous values, and returns the number of bytes
+    /// copied.
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let size = self.user_ptr.write_bytes_at(self.offset, data)?;
+
+        self.offset += size;
+
+        Ok(size)
+    }
+
+    /// Returns the number of bytes that have been written so far.
+    pub fn written(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of bytes that are left to be written.
+    pub fn remaining(&self) -> usize {
+        self.user_ptr.write_size().saturating_sub(self.offset)
+    }
+}
+
+/// Checks that `buffer` can be borrowed as `size` bytes that are aligned for `T`. Mapped user pages
+/// are only borrowed if `mapped` is set.
+fn view<T>(buffer: &Buffer, size: usize, mapped: bool) -> Result<*mut T, Error> {
+    match buffer.kind {
+        BufferKind::System => (),
+        BufferKind::Mapped if mapped => (),
+        _ => return Err(Error::INVALID_PARAMETER),
+    }
+
+    check_view::<T>(buffer.ptr, buffer.size, size)?;
+
+    // An empty buffer may be null, which is never a valid reference, even to a zero-sized `T`.
+    if buffer.ptr.is_null() {
+        return Ok(NonNull::<T>::dangling().as_ptr());
+    }
+
+    Ok(buffer.ptr as *mut T)
+}
+
+fn check_view<T>(ptr: *const cty::c_void, buffer_size: usize, size: usize) -> Result<(), Error> {
+    if buffer_size < size {
+        return Err(Error::INVALID_USER_BUFFER);
+    }
+
+    if buffer_size == 0 {
+        return Ok(());
+    }
+
+    if ptr.is_null() {
+        return Err(Error::INVALID_PARAMETER);
+    }
+
+    if ptr as usize % align_of::<T>() != 0 {
+        return Err(Error::DATATYPE_MISALIGNMENT);
+    }
+
+    Ok(())
+}
+
+fn slice_len<T>(size: usize) -> usize {
+    if size_of::<T>() == 0 {
+        0
+    } else {
+        size / size_of::<T>()
+    }
+}
+
+fn round_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
 }